@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+
+use bincode::Options;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crawl_data::{CachedListing, DownloadData, FailedDirectory, FileHeader},
+    network::PendingDirectory,
+};
+
+/// The bytes every checkpoint file starts with.
+const MAGIC: &[u8; 8] = b"ATARCKPT";
+
+/// The version of the checkpoint file format, increased whenever `CrawlCheckpoint` or the types
+/// it holds change.
+///
+/// Checkpoints only live until their crawl completes, so older versions aren't migrated. The
+/// crawl is started again instead.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The progress of a crawl, saved periodically so an interrupted scan can be resumed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CrawlCheckpoint {
+    /// URL the crawl started from.
+    pub root_url: String,
+    /// URLs of the directories that have been listed.
+    pub visited: HashSet<String>,
+    /// Directories that still have to be listed.
+    pub pending: Vec<PendingDirectory>,
+    pub download_list: Vec<DownloadData>,
+    pub directories_to_create: Vec<String>,
+    /// Listings of the visited directories, to be reused by the next crawl.
+    pub listings: HashMap<String, CachedListing>,
+    /// Visited directories that couldn't be listed.
+    pub failed_directories: Vec<FailedDirectory>,
+    pub total_size: u64,
+    pub saved_at: DateTime<Utc>,
+}
+
+impl CrawlCheckpoint {
+    /// Creates the checkpoint of a crawl that hasn't started yet.
+    pub fn new(root_url: String) -> Self {
+        Self {
+            pending: vec![PendingDirectory {
+                url: root_url.clone(),
+                relative_path: String::new(),
+            }],
+            root_url,
+            visited: HashSet::new(),
+            download_list: Vec::new(),
+            directories_to_create: Vec::new(),
+            listings: HashMap::new(),
+            failed_directories: Vec::new(),
+            total_size: 0,
+            saved_at: Utc::now(),
+        }
+    }
+
+    /// Loads a checkpoint from the file.
+    pub async fn load(path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let data = tokio::fs::read(path).await?;
+        Self::from_bytes(&data).map_err(|e| {
+            format!(
+                "Failed to load the crawl checkpoint {}: {}. Run the crawl again without --resume to start over.",
+                path, e
+            )
+            .into()
+        })
+    }
+
+    /// Saves the checkpoint to the file.
+    ///
+    /// The data is written to a temporary file first and then renamed over the old checkpoint,
+    /// so a crash while saving never leaves a truncated checkpoint behind.
+    pub async fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut data = MAGIC.to_vec();
+        bincode::serialize_into(&mut data, &FileHeader::new(FORMAT_VERSION))?;
+        bincode::serialize_into(&mut data, self)?;
+
+        let temp_path = format!("{}.tmp", path);
+        tokio::fs::write(&temp_path, data).await?;
+        tokio::fs::rename(&temp_path, path).await?;
+        Ok(())
+    }
+
+    /// Decodes the contents of a checkpoint file.
+    fn from_bytes(data: &[u8]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(mut body) = data.strip_prefix(MAGIC) else {
+            return Err("not a crawl checkpoint, or written by an older version".into());
+        };
+
+        let header: FileHeader = bincode::deserialize_from(&mut body)?;
+        if header.format_version != FORMAT_VERSION {
+            return Err(format!(
+                "written by version {} in checkpoint format version {}, but this version only reads format version {}",
+                header.tool_version, header.format_version, FORMAT_VERSION
+            )
+            .into());
+        }
+
+        Ok(bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .reject_trailing_bytes()
+            .deserialize(body)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn saves_and_loads_checkpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crawl.checkpoint");
+        let path = path.to_str().unwrap();
+
+        let mut checkpoint = CrawlCheckpoint::new("https://example.com/pub/".to_string());
+        checkpoint
+            .visited
+            .insert("https://example.com/pub/".to_string());
+        checkpoint.total_size = 1234;
+        checkpoint.save(path).await.unwrap();
+
+        let loaded = CrawlCheckpoint::load(path).await.unwrap();
+        assert_eq!(loaded.root_url, checkpoint.root_url);
+        assert_eq!(loaded.visited, checkpoint.visited);
+        assert_eq!(loaded.pending.len(), 1);
+        assert_eq!(loaded.total_size, 1234);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let checkpoint = CrawlCheckpoint::new("https://example.com/pub/".to_string());
+
+        // Written without a header, like before the format was versioned
+        let error =
+            CrawlCheckpoint::from_bytes(&bincode::serialize(&checkpoint).unwrap()).unwrap_err();
+        assert!(
+            error.to_string().contains("not a crawl checkpoint"),
+            "{}",
+            error
+        );
+
+        let mut data = MAGIC.to_vec();
+        bincode::serialize_into(
+            &mut data,
+            &FileHeader {
+                format_version: FORMAT_VERSION + 1,
+                tool_version: "9.0.0".to_string(),
+            },
+        )
+        .unwrap();
+        bincode::serialize_into(&mut data, &checkpoint).unwrap();
+
+        let error = CrawlCheckpoint::from_bytes(&data).unwrap_err();
+        assert!(
+            error.to_string().contains("written by version 9.0.0"),
+            "{}",
+            error
+        );
+    }
+}
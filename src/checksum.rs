@@ -0,0 +1,343 @@
+use std::{collections::HashMap, fmt::Display};
+
+use futures::StreamExt;
+use md5::Md5;
+use percent_encoding::percent_decode_str;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+use tracing::{debug, info, warn};
+
+use crate::{crawl_data::DownloadData, network::fetch_url};
+
+/// A hash algorithm used by published checksum files, ordered from weakest to strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Detects checksum files by their name, returning the algorithm and whether the file is a
+    /// sidecar (`file.iso.sha256`) rather than a manifest of a whole directory (`SHA256SUMS`).
+    fn from_file_name(name: &str) -> Option<(Self, bool)> {
+        let name = name.to_ascii_lowercase();
+        match name.as_str() {
+            "sha256sums" | "sha256sums.txt" | "sha256sum.txt" => {
+                Some((HashAlgorithm::Sha256, false))
+            }
+            "md5sums" | "md5sums.txt" | "md5sum.txt" => Some((HashAlgorithm::Md5, false)),
+            _ if name.ends_with(".sha256") => Some((HashAlgorithm::Sha256, true)),
+            _ if name.ends_with(".md5") => Some((HashAlgorithm::Md5, true)),
+            _ => None,
+        }
+    }
+
+    /// The length of a digest in hexadecimal characters.
+    fn hex_len(self) -> usize {
+        match self {
+            HashAlgorithm::Md5 => 32,
+            HashAlgorithm::Sha256 => 64,
+        }
+    }
+
+    /// The name used in BSD-style checksum lines like `SHA256 (file) = ...`.
+    fn bsd_name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "MD5",
+            HashAlgorithm::Sha256 => "SHA256",
+        }
+    }
+}
+
+/// The expected digest of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: HashAlgorithm,
+    /// The digest in lowercase hexadecimal.
+    pub digest: String,
+}
+
+impl Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            self.algorithm.bsd_name().to_lowercase(),
+            self.digest
+        )
+    }
+}
+
+/// The published checksums of the files in a download list, keyed by their decoded path
+/// relative to the output directory.
+#[derive(Debug, Default)]
+pub struct ChecksumIndex {
+    checksums: HashMap<String, Checksum>,
+}
+
+impl ChecksumIndex {
+    /// Finds the checksum files (`SHA256SUMS`, `MD5SUMS`, `*.sha256`, `*.md5`) in the download
+    /// list, and downloads and parses them.
+    ///
+    /// Checksum files that can't be fetched are logged and ignored, so a broken manifest never
+    /// stops the downloads themselves.
+    pub async fn fetch(client: &Client, files: &[DownloadData], concurrency: usize) -> Self {
+        let manifests: Vec<_> = files
+            .iter()
+            .filter_map(|file| {
+                let path = percent_decode_str(&file.output_dir)
+                    .decode_utf8_lossy()
+                    .into_owned();
+                let name = path.rsplit('/').next().unwrap_or(&path);
+                let (algorithm, sidecar) = HashAlgorithm::from_file_name(name)?;
+                Some((file, path, algorithm, sidecar))
+            })
+            .collect();
+
+        let mut index = ChecksumIndex::default();
+        if manifests.is_empty() {
+            return index;
+        }
+
+        info!("Fetching {} checksum files...", manifests.len());
+
+        let mut responses = futures::stream::iter(manifests)
+            .map(|(file, path, algorithm, sidecar)| async move {
+                let content = async {
+                    let response = fetch_url(client, &file.url).await?.error_for_status()?;
+                    Ok::<_, Box<dyn std::error::Error + Send + Sync>>(response.text().await?)
+                }
+                .await;
+                (file, path, algorithm, sidecar, content)
+            })
+            .buffer_unordered(concurrency.max(1));
+
+        while let Some((file, path, algorithm, sidecar, content)) = responses.next().await {
+            let content = match content {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("Failed to fetch checksum file {}: {}", file.url, e);
+                    continue;
+                }
+            };
+
+            let directory = path.rsplit_once('/').map_or("", |(directory, _)| directory);
+
+            for (name, digest) in parse_checksums(&content, algorithm) {
+                let target = match name {
+                    Some(name) => match join_relative(directory, &name) {
+                        Some(target) => target,
+                        None => {
+                            debug!("Ignoring checksum for {} in {}", name, file.url);
+                            continue;
+                        }
+                    },
+                    // A sidecar holding only the digest belongs to the file it's named after
+                    None if sidecar => path[..path.rfind('.').unwrap_or(path.len())].to_string(),
+                    None => continue,
+                };
+                index.insert(target, Checksum { algorithm, digest });
+            }
+        }
+
+        info!("Found checksums for {} files", index.checksums.len());
+
+        index
+    }
+
+    /// Returns the expected checksum of the file at the relative path.
+    pub fn get(&self, path: &str) -> Option<&Checksum> {
+        self.checksums.get(path)
+    }
+
+    /// Adds a checksum, keeping the strongest one if a file is listed more than once.
+    fn insert(&mut self, path: String, checksum: Checksum) {
+        match self.checksums.get(&path) {
+            Some(existing) if existing.algorithm > checksum.algorithm => {}
+            _ => {
+                self.checksums.insert(path, checksum);
+            }
+        }
+    }
+}
+
+/// Parses the lines of a checksum file, in GNU (`digest  name`, `digest *name`) or BSD
+/// (`SHA256 (name) = digest`) format. Sidecar files may also hold just the digest, in which case
+/// the name is `None`.
+fn parse_checksums(content: &str, algorithm: HashAlgorithm) -> Vec<(Option<String>, String)> {
+    let bsd_prefix = format!("{} (", algorithm.bsd_name());
+    let is_digest = |digest: &str| {
+        digest.len() == algorithm.hex_len() && digest.chars().all(|c| c.is_ascii_hexdigit())
+    };
+
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (name, digest) = if let Some(rest) = line.strip_prefix(&bsd_prefix) {
+                let (name, digest) = rest.rsplit_once(") = ")?;
+                (Some(name), digest.trim())
+            } else {
+                match line.split_once(char::is_whitespace) {
+                    Some((digest, name)) => {
+                        let name = name.trim_start();
+                        (Some(name.strip_prefix('*').unwrap_or(name)), digest)
+                    }
+                    None => (None, line),
+                }
+            };
+
+            is_digest(digest).then(|| (name.map(str::to_string), digest.to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+/// Joins a name from a checksum file to the directory the file is in. Names leaving the
+/// directory are rejected.
+fn join_relative(directory: &str, name: &str) -> Option<String> {
+    let name = name.trim_start_matches("./");
+    if name.is_empty() || name.starts_with('/') || name.split('/').any(|segment| segment == "..") {
+        return None;
+    }
+
+    if directory.is_empty() {
+        Some(name.to_string())
+    } else {
+        Some(format!("{}/{}", directory, name))
+    }
+}
+
+/// Hashes a file as it is downloaded.
+pub enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Hashes the contents of a file, such as the part of a download that is being resumed.
+    pub async fn update_from_file(&mut self, path: &str) -> std::io::Result<()> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.update(&buffer[..read]);
+        }
+    }
+
+    /// Returns the digest in lowercase hexadecimal.
+    pub fn finalize(self) -> String {
+        let digest = match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+        };
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256_EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const MD5_ABC: &str = "900150983cd24fb0d6963f7d28e17f72";
+
+    fn named(name: &str, digest: &str) -> (Option<String>, String) {
+        (Some(name.to_string()), digest.to_string())
+    }
+
+    #[test]
+    fn parses_gnu_checksums() {
+        let content = format!(
+            "# SHA256 checksums\n\
+             {SHA256_EMPTY}  empty.txt\n\
+             {}  *abc.bin\n\
+             {SHA256_ABC}  name with spaces.iso\n\
+             \n\
+             not-a-digest  broken.txt\n\
+             {MD5_ABC}  too-short.txt\n",
+            SHA256_ABC.to_ascii_uppercase()
+        );
+
+        assert_eq!(
+            parse_checksums(&content, HashAlgorithm::Sha256),
+            [
+                named("empty.txt", SHA256_EMPTY),
+                named("abc.bin", SHA256_ABC),
+                named("name with spaces.iso", SHA256_ABC),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_bsd_checksums() {
+        let content = format!(
+            "SHA256 (abc.bin) = {SHA256_ABC}\n\
+             SHA256 (dir/file (1).iso) = {SHA256_EMPTY}\n\
+             MD5 (abc.bin) = {MD5_ABC}\n"
+        );
+
+        assert_eq!(
+            parse_checksums(&content, HashAlgorithm::Sha256),
+            [
+                named("abc.bin", SHA256_ABC),
+                named("dir/file (1).iso", SHA256_EMPTY),
+            ]
+        );
+        assert_eq!(
+            parse_checksums(&content, HashAlgorithm::Md5),
+            [named("abc.bin", MD5_ABC)]
+        );
+    }
+
+    #[test]
+    fn parses_sidecar_checksums() {
+        assert_eq!(
+            parse_checksums(&format!("{SHA256_ABC}\n"), HashAlgorithm::Sha256),
+            [(None, SHA256_ABC.to_string())]
+        );
+        assert_eq!(
+            parse_checksums(&format!("{MD5_ABC} *abc.bin\r\n"), HashAlgorithm::Md5),
+            [named("abc.bin", MD5_ABC)]
+        );
+        assert!(parse_checksums(SHA256_ABC, HashAlgorithm::Md5).is_empty());
+    }
+
+    #[test]
+    fn joins_relative_names() {
+        assert_eq!(join_relative("", "a.iso").as_deref(), Some("a.iso"));
+        assert_eq!(
+            join_relative("pub/iso", "./a.iso").as_deref(),
+            Some("pub/iso/a.iso")
+        );
+        assert_eq!(
+            join_relative("pub", "sub/a.iso").as_deref(),
+            Some("pub/sub/a.iso")
+        );
+
+        assert_eq!(join_relative("pub", "../a.iso"), None);
+        assert_eq!(join_relative("pub", "sub/../../a.iso"), None);
+        assert_eq!(join_relative("pub", "/etc/passwd"), None);
+        assert_eq!(join_relative("pub", ""), None);
+        assert_eq!(join_relative("pub", "./"), None);
+    }
+}
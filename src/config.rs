@@ -1,3 +1,12 @@
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use glob::Pattern;
+use reqwest::{header::HeaderValue, Url};
 use serde::{Deserialize, Serialize};
 
 /// Default path to the configuration file.
@@ -15,16 +24,83 @@ pub const DEFAULT_OUTPUT_DIR: &str = "./output";
 /// Number of concurrent downloads to perform.
 pub const DEFAULT_CONCURRENT_DOWNLOADS: usize = 30;
 
+/// Number of directories to list concurrently while crawling.
+pub const DEFAULT_CRAWL_CONCURRENCY: usize = 8;
+
+/// Number of seconds between saves of the crawl checkpoint.
+pub const DEFAULT_CHECKPOINT_INTERVAL_SECS: u64 = 30;
+
+/// Number of attempts made to download a file before giving up.
+pub const DEFAULT_RETRY_ATTEMPTS: usize = 5;
+
+/// Delay before the first download retry, doubled on every further attempt.
+pub const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 500;
+
+/// Upper bound for the delay between download retries.
+pub const DEFAULT_RETRY_MAX_DELAY_MS: u64 = 30_000;
+
+/// HTTP status codes that are worth retrying a download for.
+pub const DEFAULT_TRANSIENT_STATUS: [u16; 7] = [408, 425, 429, 500, 502, 503, 504];
+
+/// Directory downloads that don't match their checksum are moved to when quarantining them.
+pub const DEFAULT_QUARANTINE_DIR: &str = "./quarantine";
+
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub url: String,
     pub user_agent: String,
     pub output_dir: String,
     pub concurrent_downloads: usize,
     pub filter: Vec<FilterRule>,
+    /// The kind of server generating the directory listings.
+    #[serde(default)]
+    pub server: ServerFlavour,
+    /// Where the directory listings are read from.
+    #[serde(default)]
+    pub backend: Backend,
+    /// Key prefix to start crawling from when using the S3 backend.
+    #[serde(default)]
+    pub s3_prefix: String,
+    /// Username for HTTP Basic authentication, sent with every request.
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Password for HTTP Basic authentication.
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Number of directories to list concurrently while crawling.
+    #[serde(default = "default_crawl_concurrency")]
+    pub crawl_concurrency: usize,
+    /// The order directories are crawled in.
+    #[serde(default)]
+    pub crawl_order: CrawlOrder,
+    /// Number of seconds between saves of the crawl checkpoint.
+    #[serde(default = "default_checkpoint_interval_secs")]
+    pub checkpoint_interval_secs: u64,
+    /// When a file that already exists locally is skipped instead of downloaded again.
+    #[serde(default)]
+    pub skip_policy: SkipPolicy,
+    /// How failed file downloads are retried.
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// Verification of downloads against published checksum files.
+    #[serde(default)]
+    pub checksums: ChecksumConfig,
+    /// Named `[site.<name>]` tables, each replacing some of the settings above for one website.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub site: BTreeMap<String, toml::Table>,
+}
+
+fn default_crawl_concurrency() -> usize {
+    DEFAULT_CRAWL_CONCURRENCY
+}
+
+fn default_checkpoint_interval_secs() -> u64 {
+    DEFAULT_CHECKPOINT_INTERVAL_SECS
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct FilterRule {
     pub rule_type: RuleType,
     pub pattern: String,
@@ -36,6 +112,117 @@ pub enum RuleType {
     Exclude,
 }
 
+/// The web server flavour that produced a directory listing, used to pick the matching parser.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ServerFlavour {
+    /// Detect the server from the `Server` header and the page markup.
+    #[default]
+    Auto,
+    Nginx,
+    Apache,
+    Lighttpd,
+    Caddy,
+    /// Follow every link on the page, treating links with a trailing slash as directories.
+    Generic,
+}
+
+/// The kind of source the files are listed from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Backend {
+    /// Directory index pages generated by a web server.
+    #[default]
+    Index,
+    /// An S3-compatible bucket (AWS S3, MinIO, ...) allowing anonymous `ListObjectsV2`
+    /// requests. The `url` must point at the bucket itself.
+    S3,
+    /// A WebDAV server (nginx dav, Apache mod_dav, rclone serve webdav, ...).
+    WebDav,
+}
+
+/// The order in which queued directories are crawled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrawlOrder {
+    /// Crawl all directories of one level before descending into the next.
+    #[default]
+    BreadthFirst,
+    /// Crawl the most recently found directory first.
+    DepthFirst,
+}
+
+/// Decides when a file that already exists in the output directory is skipped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SkipPolicy {
+    /// Skip any file that exists.
+    #[default]
+    Exists,
+    /// Skip files whose size matches the crawled size.
+    SizeMatch,
+    /// Skip files unless the server's modification time is newer than the local one. Times
+    /// read from listing pages may be in the server's local time, so for those files the time
+    /// is requested with a HEAD request.
+    Newer,
+    /// Never skip, always download every file again.
+    Always,
+}
+
+/// Retry settings for file downloads.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Number of attempts per file, including the first one.
+    pub attempts: usize,
+    /// Delay before the first retry in milliseconds, doubled on every further attempt.
+    pub base_delay_ms: u64,
+    /// Upper bound for the delay between retries in milliseconds.
+    pub max_delay_ms: u64,
+    /// HTTP status codes that are retried. Any other error status fails the download at once.
+    pub transient_status: Vec<u16>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: DEFAULT_RETRY_ATTEMPTS,
+            base_delay_ms: DEFAULT_RETRY_BASE_DELAY_MS,
+            max_delay_ms: DEFAULT_RETRY_MAX_DELAY_MS,
+            transient_status: DEFAULT_TRANSIENT_STATUS.to_vec(),
+        }
+    }
+}
+
+/// Settings for verifying downloads against published checksum files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChecksumConfig {
+    /// Look for `SHA256SUMS`, `MD5SUMS`, `*.sha256` and `*.md5` files in the crawled listing,
+    /// and verify the downloaded files against them.
+    pub enabled: bool,
+    /// What happens to a download that doesn't match its checksum, before it is retried.
+    pub on_mismatch: MismatchAction,
+    /// Directory mismatched downloads are moved to with the `Quarantine` action.
+    pub quarantine_dir: String,
+}
+
+impl Default for ChecksumConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            on_mismatch: MismatchAction::Delete,
+            quarantine_dir: DEFAULT_QUARANTINE_DIR.to_string(),
+        }
+    }
+}
+
+/// What happens to a download that doesn't match its checksum.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MismatchAction {
+    /// Delete the downloaded data.
+    #[default]
+    Delete,
+    /// Move the downloaded data to the quarantine directory, keeping its relative path.
+    Quarantine,
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -47,6 +234,18 @@ impl Default for Config {
                 rule_type: RuleType::Include,
                 pattern: "*".to_string(), // Include all files by default
             }],
+            server: ServerFlavour::Auto,
+            backend: Backend::Index,
+            s3_prefix: String::new(),
+            username: None,
+            password: None,
+            crawl_concurrency: DEFAULT_CRAWL_CONCURRENCY,
+            crawl_order: CrawlOrder::BreadthFirst,
+            checkpoint_interval_secs: DEFAULT_CHECKPOINT_INTERVAL_SECS,
+            skip_policy: SkipPolicy::Exists,
+            retry: RetryConfig::default(),
+            checksums: ChecksumConfig::default(),
+            site: BTreeMap::new(),
         }
     }
 }
@@ -57,4 +256,225 @@ impl Config {
         let config: Config = toml::from_str(&config)?;
         Ok(config)
     }
+
+    /// Writes the configuration to the file. Unless `replace` is set, the file must not exist
+    /// yet, which is checked while creating it, so an existing file is never overwritten.
+    pub fn write(&self, path: &str, replace: bool) -> io::Result<()> {
+        let contents = toml::to_string_pretty(self).map_err(io::Error::other)?;
+        let mut file = if replace {
+            File::create(path)?
+        } else {
+            OpenOptions::new().write(true).create_new(true).open(path)?
+        };
+        file.write_all(contents.as_bytes())
+    }
+
+    /// Returns the configuration of a site: the settings of its `[site.<name>]` table over the
+    /// global ones. Tables like `[site.<name>.retry]` only replace the settings they contain.
+    pub fn for_site(&self, name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let site = self
+            .site
+            .get(name)
+            .ok_or_else(|| format!("There is no [site.{}] table in the configuration", name))?;
+
+        let mut table = toml::Table::try_from(self)?;
+        table.remove("site");
+        merge_table(&mut table, site);
+
+        table
+            .try_into()
+            .map_err(|e| format!("Invalid [site.{}] table: {}", name, e).into())
+    }
+
+    /// Checks the settings that can't be checked while reading the file, returning a message
+    /// naming the setting for each invalid one.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        match Url::parse(&self.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            Ok(url) => errors.push(format!(
+                "url: unsupported scheme `{}`, expected http or https",
+                url.scheme()
+            )),
+            Err(e) => errors.push(format!("url: `{}` is not a valid URL: {}", self.url, e)),
+        }
+
+        if HeaderValue::from_str(&self.user_agent).is_err() {
+            errors.push("user_agent: contains characters that aren't allowed in a header".into());
+        }
+
+        // Nothing would ever be listed or downloaded with a limit of zero
+        for (name, value) in [
+            ("concurrent_downloads", self.concurrent_downloads),
+            ("crawl_concurrency", self.crawl_concurrency),
+            ("retry.attempts", self.retry.attempts),
+        ] {
+            if value == 0 {
+                errors.push(format!("{}: must be above zero", name));
+            }
+        }
+
+        for (i, rule) in self.filter.iter().enumerate() {
+            if let Err(e) = Pattern::new(&rule.pattern) {
+                errors.push(format!(
+                    "filter[{}].pattern: `{}` is not a valid glob pattern: {}",
+                    i, rule.pattern, e
+                ));
+            }
+        }
+
+        errors
+    }
+}
+
+/// Merges the values of `other` into `table`, replacing everything but nested tables, which are
+/// merged in turn.
+fn merge_table(table: &mut toml::Table, other: &toml::Table) {
+    for (key, value) in other {
+        match (table.get_mut(key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(other)) => {
+                merge_table(table, other)
+            }
+            _ => {
+                table.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Where the configuration is read from, and the settings overriding it. The settings are
+/// layered: defaults, then the configuration file, then `ATAR_*` environment variables, then
+/// the command line.
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Configuration")]
+pub struct ConfigArgs {
+    /// Path to the configuration file [default: ./config.toml]
+    #[arg(
+        id = "config",
+        long = "config",
+        value_name = "PATH",
+        env = "ATAR_CONFIG",
+        global = true
+    )]
+    pub path: Option<String>,
+
+    /// Use the settings of this `[site.<name>]` table of the configuration file, and its own
+    /// crawl data file
+    #[arg(long, env = "ATAR_SITE", global = true)]
+    pub site: Option<String>,
+
+    /// The URL containing the files
+    #[arg(long, env = "ATAR_URL", global = true)]
+    pub url: Option<String>,
+
+    /// Output directory for the downloaded files
+    #[arg(long, env = "ATAR_OUTPUT_DIR", global = true)]
+    pub output_dir: Option<String>,
+
+    /// Number of concurrent downloads to perform
+    #[arg(long, env = "ATAR_CONCURRENT_DOWNLOADS", global = true)]
+    pub concurrent_downloads: Option<usize>,
+
+    /// The User-Agent header to use for the requests
+    #[arg(long, env = "ATAR_USER_AGENT", global = true)]
+    pub user_agent: Option<String>,
+
+    /// Only download the files matching this glob pattern, replacing the filters of the
+    /// configuration file. Can be repeated, or separated by commas
+    #[arg(long, env = "ATAR_INCLUDE", value_delimiter = ',', global = true)]
+    pub include: Vec<String>,
+
+    /// Skip the files matching this glob pattern, replacing the filters of the configuration
+    /// file. Can be repeated, or separated by commas
+    #[arg(long, env = "ATAR_EXCLUDE", value_delimiter = ',', global = true)]
+    pub exclude: Vec<String>,
+}
+
+impl ConfigArgs {
+    /// Returns the path to the configuration file, the default one unless it was given.
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or(DEFAULT_CONFIG_PATH)
+    }
+
+    /// Reads the configuration file, of the site if one is given, applies the overrides and
+    /// validates the result. If any setting is overridden and the default configuration file
+    /// doesn't exist, the defaults are used instead. A configuration file that was given must
+    /// exist.
+    pub fn load(&self, site: Option<&str>) -> Result<Config, Box<dyn std::error::Error>> {
+        let path = self.path();
+        let mut config = if site.is_none()
+            && self.path.is_none()
+            && self.has_overrides()
+            && !Path::new(path).exists()
+        {
+            Config::default()
+        } else if !Path::new(path).exists() {
+            return Err(format!("Configuration file not found: {}", path).into());
+        } else {
+            Config::from_file(path)?
+        };
+        if let Some(site) = site {
+            config = config.for_site(site)?;
+        }
+        self.apply(&mut config);
+
+        let errors = config.validate();
+        if !errors.is_empty() {
+            let prefix = site
+                .map(|site| format!("[site.{}] ", site))
+                .unwrap_or_default();
+            let errors: Vec<_> = errors.iter().map(|e| format!("{}{}", prefix, e)).collect();
+            return Err(errors.join("\n").into());
+        }
+
+        Ok(config)
+    }
+
+    /// Returns true if any setting is given on the command line or in the environment.
+    pub fn has_overrides(&self) -> bool {
+        self.url.is_some()
+            || self.output_dir.is_some()
+            || self.concurrent_downloads.is_some()
+            || self.user_agent.is_some()
+            || !self.include.is_empty()
+            || !self.exclude.is_empty()
+    }
+
+    /// Replaces the settings of the configuration with the overridden ones.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(url) = &self.url {
+            config.url = url.clone();
+        }
+        if let Some(output_dir) = &self.output_dir {
+            config.output_dir = output_dir.clone();
+        }
+        if let Some(concurrent_downloads) = self.concurrent_downloads {
+            config.concurrent_downloads = concurrent_downloads;
+        }
+        if let Some(user_agent) = &self.user_agent {
+            config.user_agent = user_agent.clone();
+        }
+
+        if !self.include.is_empty() || !self.exclude.is_empty() {
+            // Only excluding files still includes everything else
+            let include = if self.include.is_empty() {
+                vec!["*".to_string()]
+            } else {
+                self.include.clone()
+            };
+
+            config.filter = include
+                .into_iter()
+                .map(|pattern| FilterRule {
+                    rule_type: RuleType::Include,
+                    pattern,
+                })
+                .chain(self.exclude.iter().map(|pattern| FilterRule {
+                    rule_type: RuleType::Exclude,
+                    pattern: pattern.clone(),
+                }))
+                .collect();
+        }
+    }
 }
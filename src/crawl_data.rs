@@ -1,40 +1,231 @@
-use std::fmt::Display;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+};
 
+use bincode::Options;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tracing::info;
 
-use crate::utils::format_size;
+use crate::{index_parser::ListingEntry, legacy, utils::format_size};
+
+/// The bytes every crawl data file starts with.
+const MAGIC: &[u8; 8] = b"ATARCRWL";
+
+/// The version of the crawl data file format, increased whenever `CrawlData` changes.
+///
+/// Files of older versions must keep loading: add a migration to `CrawlData::from_bytes` when
+/// increasing it.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The header written after the magic bytes, before the crawl data itself. Crawl checkpoints
+/// start with the same header.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileHeader {
+    pub format_version: u32,
+    /// Version of the program that wrote the file.
+    pub tool_version: String,
+}
+
+impl FileHeader {
+    /// Creates the header of a file written by this version in the given format version.
+    pub fn new(format_version: u32) -> Self {
+        Self {
+            format_version,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
 
 // Define the CrawlData struct
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CrawlData {
     pub download_list: Vec<DownloadData>,
     pub total_size: u64,
+    /// The crawled directories, relative to the output directory and percent-encoded like the
+    /// paths of the files, so the crawl data can be downloaded to any output directory.
     pub directories_to_create: Vec<String>,
     pub saved_at: DateTime<Utc>,
+    /// Why the files in `download_list` failed to download, if this is a failures file.
+    pub failures: Vec<FailedDownload>,
+    /// The listings of the crawled directories, keyed by their URL, so the next crawl can skip
+    /// the ones that are unchanged.
+    pub listings: HashMap<String, CachedListing>,
+    /// The directories the crawl couldn't list, whose files are missing from `download_list`.
+    pub failed_directories: Vec<FailedDirectory>,
+}
+
+impl CrawlData {
+    /// Builds crawl data for a list of files that wasn't crawled, creating only the directories
+    /// the files are in.
+    pub fn from_download_list(download_list: Vec<DownloadData>) -> Self {
+        let total_size = download_list.iter().filter_map(|file| file.size).sum();
+
+        let directories_to_create = download_list
+            .iter()
+            .filter_map(|file| file.output_dir.rsplit_once('/'))
+            .map(|(parent, _)| parent.to_string())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        Self {
+            download_list,
+            total_size,
+            directories_to_create,
+            saved_at: Utc::now(),
+            failures: Vec::new(),
+            listings: HashMap::new(),
+            failed_directories: Vec::new(),
+        }
+    }
+
+    /// Builds the crawl data of a failures file, holding only the files that failed to download.
+    pub fn from_failures(failed: Vec<(DownloadData, FailedDownload)>) -> Self {
+        let (download_list, failures): (Vec<_>, Vec<_>) = failed.into_iter().unzip();
+
+        Self {
+            failures,
+            ..Self::from_download_list(download_list)
+        }
+    }
+
+    /// Loads crawl data from the file, migrating files written by older versions.
+    pub async fn load(path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let data = tokio::fs::read(path)
+            .await
+            .map_err(|e| format!("Failed to load {}: {}", path, e))?;
+        Self::from_bytes(&data).map_err(|e| format!("Failed to load {}: {}", path, e).into())
+    }
+
+    /// Saves the crawl data to the file, in the current format version.
+    pub async fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut data = MAGIC.to_vec();
+        bincode::serialize_into(&mut data, &FileHeader::new(FORMAT_VERSION))?;
+        bincode::serialize_into(&mut data, self)?;
+
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+
+    /// Decodes the contents of a crawl data file.
+    fn from_bytes(data: &[u8]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(mut body) = data.strip_prefix(MAGIC) else {
+            // Files without a header were written before the format was versioned
+            return match legacy::migrate(data) {
+                Some(crawl_data) => {
+                    info!(
+                        "Migrated crawl data written by version 1.0.1, save it again to upgrade it"
+                    );
+                    Ok(crawl_data)
+                }
+                None => Err("not a crawl data file, or written in an unknown format".into()),
+            };
+        };
+
+        let header: FileHeader = bincode::deserialize_from(&mut body)?;
+
+        match header.format_version {
+            FORMAT_VERSION => Ok(bincode::DefaultOptions::new()
+                .with_fixint_encoding()
+                .reject_trailing_bytes()
+                .deserialize(body)?),
+            version if version > FORMAT_VERSION => Err(format!(
+                "written by version {} in format version {}, but this version only reads up to format version {}. Please update atar-rocks-downloader.",
+                header.tool_version, version, FORMAT_VERSION
+            )
+            .into()),
+            version => Err(format!("unknown format version {}", version).into()),
+        }
+    }
 }
 
 impl Display for CrawlData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
+        writeln!(
             f,
-            "CrawlData: {} files, {} ({} bytes)\nSaved at: {}\n\n# Directories to create:\n{}\n\n# Files to download:\n{}",
+            "CrawlData: {} files, {} ({} bytes)\nSaved at: {}\n",
             self.download_list.len(),
             format_size(self.total_size),
             self.total_size,
             self.saved_at,
-            self.directories_to_create
-                .iter()
-                .fold(String::new(), |acc, dir| { acc + &format!("{}\n", dir) }),
-            self.download_list.iter().fold(String::new(), |acc, file| { acc + &format!("{}\n", file) }),
-        )
+        )?;
+
+        writeln!(f, "# Directories to create:")?;
+        for directory in &self.directories_to_create {
+            writeln!(f, "{}", directory)?;
+        }
+
+        writeln!(f, "\n# Files to download:")?;
+        for file in &self.download_list {
+            writeln!(f, "{}", file)?;
+        }
+
+        if !self.failures.is_empty() {
+            writeln!(f, "\n# Failed downloads:")?;
+            for failure in &self.failures {
+                writeln!(f, "{}", failure)?;
+            }
+        }
+
+        if !self.failed_directories.is_empty() {
+            writeln!(f, "\n# Directories that couldn't be listed:")?;
+            for directory in &self.failed_directories {
+                writeln!(f, "{}", directory)?;
+            }
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadData {
     pub url: String,
     pub output_dir: String,
+    /// Size of the file in bytes, if known.
+    pub size: Option<u64>,
+    /// Whether `size` is exact, rather than rounded by the directory listing.
+    pub size_exact: bool,
+    /// Last modification time reported by the server, if known.
+    pub modified: Option<DateTime<Utc>>,
+    /// Whether `modified` is exact, rather than read from a listing in the server's local time.
+    pub modified_exact: bool,
+    /// Entity tag reported by the server, if known.
+    pub etag: Option<String>,
+}
+
+impl DownloadData {
+    /// Returns the value for an `If-Range` header, so a resumed download only continues if the
+    /// file is unchanged. Weak entity tags and listing dates (which are often rounded to the
+    /// minute) can't be used for this.
+    pub fn range_validator(&self) -> Option<&str> {
+        self.etag.as_deref().filter(|etag| !etag.starts_with("W/"))
+    }
+
+    /// Returns the modification time, if it is exact.
+    pub fn exact_modified(&self) -> Option<DateTime<Utc>> {
+        self.modified.filter(|_| self.modified_exact)
+    }
+
+    /// Returns true if a local file of the given size matches the crawled size.
+    ///
+    /// Sizes rounded by the listing (like `1.2K` or `15M`) can be off by up to one unit, so
+    /// they match any local size within the unit of the listed size.
+    pub fn size_matches(&self, local_size: u64) -> bool {
+        match self.size {
+            Some(size) if self.size_exact => size == local_size,
+            Some(size) => {
+                let mut unit: u64 = 1;
+                while unit < 1 << 40 && size >= unit * 1024 {
+                    unit *= 1024;
+                }
+                size.abs_diff(local_size) < unit
+            }
+            None => false,
+        }
+    }
 }
 
 impl Display for DownloadData {
@@ -42,3 +233,62 @@ impl Display for DownloadData {
         write!(f, "{} -> {}", self.url, self.output_dir)
     }
 }
+
+/// The entries of a directory listing, before filtering, with the validators of the listing page, so a later crawl
+/// can reuse them if the server answers a conditional request with `304 Not Modified`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedListing {
+    /// `ETag` of the listing page, if the server sent one.
+    pub etag: Option<String>,
+    /// `Last-Modified` of the listing page, if the server sent one.
+    pub last_modified: Option<String>,
+    pub entries: Vec<ListingEntry>,
+}
+
+/// A file that failed to download, and why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedDownload {
+    pub url: String,
+    pub kind: FailureKind,
+    /// HTTP status code the server answered with, if the failure was caused by one.
+    pub status: Option<u16>,
+    /// The error message.
+    pub message: String,
+}
+
+impl Display for FailedDownload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.url, self.message)
+    }
+}
+
+/// A directory the crawl couldn't list, and why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedDirectory {
+    pub url: String,
+    /// Path of the directory relative to the output directory (percent-encoded).
+    pub path: String,
+    /// The error message.
+    pub message: String,
+}
+
+impl Display for FailedDirectory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.url, self.message)
+    }
+}
+
+/// The kind of error a download failed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum FailureKind {
+    /// The server answered with an error status.
+    Status,
+    /// The request failed or the connection broke off.
+    Network,
+    /// The file couldn't be written.
+    Io,
+    /// The downloaded data didn't match its published checksum.
+    Checksum,
+    /// The download task panicked.
+    Panic,
+}
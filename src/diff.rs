@@ -0,0 +1,149 @@
+use std::{collections::HashMap, fmt::Display};
+
+use chrono::{DateTime, Utc};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+
+use crate::{
+    crawl_data::{CrawlData, DownloadData},
+    utils::format_size,
+};
+
+/// The changes between two crawls of the same tree.
+#[derive(Debug, Serialize)]
+pub struct CrawlDiff {
+    pub old_saved_at: DateTime<Utc>,
+    pub new_saved_at: DateTime<Utc>,
+    /// Files that are only in the new crawl.
+    pub added: Vec<DiffEntry>,
+    /// Files that are only in the old crawl.
+    pub removed: Vec<DiffEntry>,
+    /// Files in both crawls whose size changed.
+    pub size_changed: Vec<SizeChange>,
+    /// Number of files in both crawls whose size didn't change.
+    pub unchanged: usize,
+    /// Change of the total size in bytes, from the old crawl to the new one.
+    pub byte_delta: i64,
+}
+
+/// A file that was added or removed.
+#[derive(Debug, Serialize)]
+pub struct DiffEntry {
+    pub path: String,
+    pub url: String,
+    pub size: Option<u64>,
+}
+
+/// A file whose size changed between the crawls.
+#[derive(Debug, Serialize)]
+pub struct SizeChange {
+    pub path: String,
+    pub url: String,
+    pub old_size: u64,
+    pub new_size: u64,
+}
+
+impl DiffEntry {
+    fn new(path: String, file: &DownloadData) -> Self {
+        Self {
+            path,
+            url: file.url.clone(),
+            size: file.size,
+        }
+    }
+}
+
+/// Compares two crawls by the relative paths of their files.
+///
+/// Files whose size is unknown in either crawl are counted as unchanged.
+pub fn diff_crawl_data(old: &CrawlData, new: &CrawlData) -> CrawlDiff {
+    let decode = |path: &str| percent_decode_str(path).decode_utf8_lossy().into_owned();
+
+    let mut old_files: HashMap<String, &DownloadData> = old
+        .download_list
+        .iter()
+        .map(|file| (decode(&file.output_dir), file))
+        .collect();
+
+    let mut diff = CrawlDiff {
+        old_saved_at: old.saved_at,
+        new_saved_at: new.saved_at,
+        added: Vec::new(),
+        removed: Vec::new(),
+        size_changed: Vec::new(),
+        unchanged: 0,
+        byte_delta: new.total_size as i64 - old.total_size as i64,
+    };
+
+    for file in &new.download_list {
+        let path = decode(&file.output_dir);
+        match old_files.remove(&path) {
+            None => diff.added.push(DiffEntry::new(path, file)),
+            Some(old_file) => match (old_file.size, file.size) {
+                (Some(old_size), Some(new_size)) if old_size != new_size => {
+                    diff.size_changed.push(SizeChange {
+                        path,
+                        url: file.url.clone(),
+                        old_size,
+                        new_size,
+                    })
+                }
+                _ => diff.unchanged += 1,
+            },
+        }
+    }
+
+    // Whatever is left of the old crawl isn't in the new one
+    diff.removed = old_files
+        .into_iter()
+        .map(|(path, file)| DiffEntry::new(path, file))
+        .collect();
+
+    diff.added.sort_by(|a, b| a.path.cmp(&b.path));
+    diff.removed.sort_by(|a, b| a.path.cmp(&b.path));
+    diff.size_changed.sort_by(|a, b| a.path.cmp(&b.path));
+
+    diff
+}
+
+impl Display for CrawlDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let size = |size: Option<u64>| size.map_or("unknown size".to_string(), format_size);
+
+        writeln!(
+            f,
+            "Comparing the crawl saved at {} with the crawl saved at {}",
+            self.old_saved_at, self.new_saved_at
+        )?;
+
+        for entry in &self.added {
+            writeln!(f, "+ {} ({})", entry.path, size(entry.size))?;
+        }
+        for entry in &self.removed {
+            writeln!(f, "- {} ({})", entry.path, size(entry.size))?;
+        }
+        for change in &self.size_changed {
+            writeln!(
+                f,
+                "~ {} ({} -> {})",
+                change.path,
+                format_size(change.old_size),
+                format_size(change.new_size)
+            )?;
+        }
+
+        let sign = if self.byte_delta < 0 { "-" } else { "+" };
+        write!(
+            f,
+            "{} added, {} removed, {} size changed, {} unchanged, {}{} ({}{} bytes)",
+            self.added.len(),
+            self.removed.len(),
+            self.size_changed.len(),
+            self.unchanged,
+            sign,
+            format_size(self.byte_delta.unsigned_abs()),
+            sign,
+            self.byte_delta.unsigned_abs()
+        )
+    }
+}
@@ -0,0 +1,132 @@
+use std::io::Write;
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use percent_encoding::percent_decode_str;
+use quick_xml::escape::escape;
+use serde::Serialize;
+
+use crate::crawl_data::{CrawlData, DownloadData};
+
+/// The formats the file list can be exported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// A JSON array with one object per file.
+    Json,
+    /// Comma-separated values with a header row.
+    Csv,
+    /// Newline-delimited JSON, one object per line.
+    Ndjson,
+    /// An input file for `aria2c -i`, placing each file in its directory.
+    Aria2,
+    /// A list of URLs for `wget -i`.
+    Wget,
+    /// A Metalink 4 document (RFC 5854) with the sizes of the files.
+    Metalink,
+}
+
+/// One file of the exported list.
+#[derive(Debug, Serialize)]
+struct ExportRow<'a> {
+    url: &'a str,
+    /// Path of the file relative to the output directory.
+    path: String,
+    size: Option<u64>,
+    modified: Option<DateTime<Utc>>,
+}
+
+impl<'a> From<&'a DownloadData> for ExportRow<'a> {
+    fn from(file: &'a DownloadData) -> Self {
+        Self {
+            url: &file.url,
+            path: percent_decode_str(&file.output_dir)
+                .decode_utf8_lossy()
+                .into_owned(),
+            size: file.size,
+            modified: file.modified,
+        }
+    }
+}
+
+/// Writes the file list of the crawl data in the format.
+///
+/// `output_dir` is the directory aria2 places the files in. The files are written one at a
+/// time, so the writer should be buffered.
+pub fn export(
+    crawl_data: &CrawlData,
+    format: ExportFormat,
+    output_dir: &str,
+    mut writer: impl Write,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let rows = crawl_data.download_list.iter().map(ExportRow::from);
+
+    match format {
+        ExportFormat::Json => {
+            writer.write_all(b"[")?;
+            for (i, row) in rows.enumerate() {
+                writer.write_all(if i == 0 { b"\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut writer, &row)?;
+            }
+            writer.write_all(b"\n]\n")?;
+        }
+        ExportFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(&mut writer);
+            for row in rows {
+                csv.serialize(row)?;
+            }
+            csv.flush()?;
+        }
+        ExportFormat::Ndjson => {
+            for row in rows {
+                serde_json::to_writer(&mut writer, &row)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        ExportFormat::Aria2 => {
+            for row in rows {
+                let (directory, name) = match row.path.rsplit_once('/') {
+                    Some((parent, name)) => (format!("{}/{}", output_dir, parent), name),
+                    None => (output_dir.to_string(), row.path.as_str()),
+                };
+                writeln!(writer, "{}\n  dir={}\n  out={}", row.url, directory, name)?;
+            }
+        }
+        ExportFormat::Wget => {
+            for row in rows {
+                writeln!(writer, "{}", row.url)?;
+            }
+        }
+        ExportFormat::Metalink => {
+            writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+            writeln!(
+                writer,
+                r#"<metalink xmlns="urn:ietf:params:xml:ns:metalink">"#
+            )?;
+            writeln!(
+                writer,
+                "  <generator>atar-rocks-downloader/{}</generator>",
+                env!("CARGO_PKG_VERSION")
+            )?;
+            writeln!(
+                writer,
+                "  <published>{}</published>",
+                crawl_data.saved_at.format("%Y-%m-%dT%H:%M:%SZ")
+            )?;
+
+            for (file, row) in crawl_data.download_list.iter().zip(rows) {
+                writeln!(writer, r#"  <file name="{}">"#, escape(&row.path))?;
+                // Clients check the size, so sizes rounded by the listing are left out
+                if let Some(size) = row.size.filter(|_| file.size_exact) {
+                    writeln!(writer, "    <size>{}</size>", size)?;
+                }
+                writeln!(writer, "    <url>{}</url>", escape(row.url))?;
+                writeln!(writer, "  </file>")?;
+            }
+
+            writeln!(writer, "</metalink>")?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
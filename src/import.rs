@@ -0,0 +1,252 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    crawl_data::{CrawlData, DownloadData},
+    utils::encode_path,
+};
+
+/// The formats a list of URLs can be imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportFormat {
+    /// One URL per line. Blank lines and lines starting with `#` are skipped.
+    Text,
+    /// CSV with a header row and a `url` column. The `path`, `size` and `modified` columns
+    /// written by `export --format csv` are used as well, if present.
+    Csv,
+}
+
+impl ImportFormat {
+    /// Guesses the format from the extension of the file.
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => ImportFormat::Csv,
+            _ => ImportFormat::Text,
+        }
+    }
+}
+
+/// One file of an imported list.
+#[derive(Debug, Deserialize)]
+struct ImportRow {
+    url: String,
+    /// Path of the file relative to the output directory (not percent-encoded).
+    #[serde(default)]
+    path: Option<String>,
+    /// Exact size of the file in bytes.
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    modified: Option<DateTime<Utc>>,
+}
+
+/// Reads a list of URLs and builds the crawl data to download them.
+///
+/// Each file is placed at its URL path relative to `base`, unless the list gives a path. URLs
+/// outside of `base` and URLs saved to the same path as an earlier one are skipped.
+pub fn import_urls(
+    path: &str,
+    format: ImportFormat,
+    base: &Url,
+) -> Result<CrawlData, Box<dyn std::error::Error + Send + Sync>> {
+    // The base is a directory, even if it was given without a trailing slash
+    let mut base = base.clone();
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+
+    let rows: Vec<ImportRow> = match format {
+        ImportFormat::Text => BufReader::new(File::open(path)?)
+            .lines()
+            .filter_map(|line| match line {
+                Ok(line) => {
+                    let line = line.trim();
+                    (!line.is_empty() && !line.starts_with('#')).then(|| {
+                        Ok(ImportRow {
+                            url: line.to_string(),
+                            path: None,
+                            size: None,
+                            modified: None,
+                        })
+                    })
+                }
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<_, _>>()?,
+        ImportFormat::Csv => csv::Reader::from_path(path)?
+            .into_deserialize()
+            .collect::<Result<_, _>>()?,
+    };
+
+    let mut download_list = Vec::new();
+    let mut paths = HashSet::new();
+
+    for row in rows {
+        let url = match Url::parse(&row.url) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("Skipping invalid URL {}: {}", row.url, e);
+                continue;
+            }
+        };
+
+        let relative_path = match row.path.as_deref().filter(|path| !path.is_empty()) {
+            Some(path) if is_relative_path(path) => encode_path(path),
+            Some(path) => {
+                warn!(
+                    "Skipping {}, as its path {} leaves the output directory",
+                    url, path
+                );
+                continue;
+            }
+            None => match relative_path(&base, &url) {
+                Some(relative_path) => relative_path,
+                None => {
+                    warn!("Skipping {}, as it isn't a file below {}", url, base);
+                    continue;
+                }
+            },
+        };
+
+        if !paths.insert(relative_path.clone()) {
+            warn!(
+                "Skipping {}, as another URL is saved to {}",
+                url, relative_path
+            );
+            continue;
+        }
+
+        download_list.push(DownloadData {
+            url: url.to_string(),
+            output_dir: relative_path,
+            size: row.size,
+            size_exact: row.size.is_some(),
+            modified: row.modified,
+            modified_exact: false,
+            etag: None,
+        });
+    }
+
+    Ok(CrawlData::from_download_list(download_list))
+}
+
+/// Returns the percent-encoded path of the URL relative to the base directory, if the URL is a
+/// file below it.
+///
+/// The URL parser resolves `..` segments, but an encoded slash (like in `..%2F`) only separates
+/// segments once the path is decoded for saving the file, so the decoded path is checked too.
+fn relative_path(base: &Url, url: &Url) -> Option<String> {
+    if url.origin() != base.origin() {
+        return None;
+    }
+
+    let relative = url.path().strip_prefix(base.path())?;
+    let decoded = percent_decode_str(relative).decode_utf8().ok()?;
+    is_relative_path(&decoded).then(|| relative.to_string())
+}
+
+/// Returns true if the path stays within the directory it is relative to.
+fn is_relative_path(path: &str) -> bool {
+    path.split('/')
+        .all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("http://127.0.0.1/pub/").unwrap()
+    }
+
+    /// Writes the list to a temporary file with the given extension and imports it.
+    fn import(list: &str, extension: &str) -> CrawlData {
+        let file = tempfile::Builder::new()
+            .suffix(extension)
+            .tempfile()
+            .unwrap();
+        std::fs::write(file.path(), list).unwrap();
+
+        let path = file.path().to_str().unwrap();
+        import_urls(path, ImportFormat::from_path(path), &base()).unwrap()
+    }
+
+    #[test]
+    fn finds_paths_relative_to_the_base() {
+        let relative = |url: &str| relative_path(&base(), &Url::parse(url).unwrap());
+
+        assert_eq!(
+            relative("http://127.0.0.1/pub/docs/a%20b.txt").as_deref(),
+            Some("docs/a%20b.txt")
+        );
+        assert_eq!(relative("http://127.0.0.1/other/a.txt"), None);
+        assert_eq!(relative("http://127.0.0.2/pub/a.txt"), None);
+        assert_eq!(relative("http://127.0.0.1/pub/docs/"), None);
+        assert_eq!(relative("http://127.0.0.1/pub/docs//a.txt"), None);
+
+        // Encoded separators and dots would leave the output directory once decoded
+        assert_eq!(
+            relative("http://127.0.0.1/pub/docs%2F..%2F..%2F..%2Fescaped.txt"),
+            None
+        );
+        assert_eq!(relative("http://127.0.0.1/pub/%2E%2E/escaped.txt"), None);
+        assert_eq!(relative("http://127.0.0.1/pub/docs/..%2Fescaped.txt"), None);
+    }
+
+    #[test]
+    fn imports_url_lists() {
+        let crawl_data = import(
+            "# Mirror list\n\
+             http://127.0.0.1/pub/docs/a.txt\n\
+             \n\
+             not a url\n\
+             http://127.0.0.1/other/b.txt\n\
+             http://127.0.0.1/pub/docs%2F..%2F..%2Fescaped.txt\n\
+             http://127.0.0.1/pub/docs/a.txt?mirror=2\n\
+             http://127.0.0.1/pub/c%20d.iso\n",
+            ".txt",
+        );
+
+        let paths: Vec<_> = crawl_data
+            .download_list
+            .iter()
+            .map(|file| file.output_dir.as_str())
+            .collect();
+        assert_eq!(paths, ["docs/a.txt", "c%20d.iso"]);
+        assert_eq!(crawl_data.directories_to_create, ["docs"]);
+        assert!(crawl_data.download_list[0].size.is_none());
+    }
+
+    #[test]
+    fn imports_csv_lists() {
+        let crawl_data = import(
+            "url,path,size,modified\n\
+             http://127.0.0.1/pub/a.txt,docs/a b.txt,12,2024-11-15T10:21:00Z\n\
+             http://127.0.0.1/pub/b.txt,../b.txt,,\n\
+             http://127.0.0.1/pub/c.txt,,,\n",
+            ".csv",
+        );
+
+        let files = &crawl_data.download_list;
+        assert_eq!(files.len(), 2);
+
+        assert_eq!(files[0].output_dir, "docs/a%20b.txt");
+        assert_eq!(files[0].size, Some(12));
+        assert!(files[0].size_exact);
+        assert!(files[0].modified.is_some());
+
+        assert_eq!(files[1].output_dir, "c.txt");
+        assert_eq!(crawl_data.total_size, 12);
+    }
+}
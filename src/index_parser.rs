@@ -0,0 +1,926 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ServerFlavour,
+    utils::{encode_path, should_skip_url},
+    xml::XmlElement,
+};
+
+/// A single entry of a directory listing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListingEntry {
+    /// Name of the entry as it appears in the URL (percent-encoded, without a trailing slash).
+    pub name: String,
+    /// Absolute URL of the entry.
+    pub url: Url,
+    /// Whether the entry is a directory.
+    pub is_dir: bool,
+    /// Size in bytes, if the listing shows one.
+    pub size: Option<u64>,
+    /// Whether `size` is exact, rather than rounded like `1.2K`.
+    pub size_exact: bool,
+    /// Last modification time, if the listing shows one.
+    pub mtime: Option<DateTime<Utc>>,
+    /// Whether `mtime` is exact, rather than shown in the server's unknown time zone.
+    pub mtime_exact: bool,
+    /// Entity tag of the file, if the listing shows one.
+    pub etag: Option<String>,
+}
+
+impl ListingEntry {
+    /// Marks the entry as a directory, adding the trailing slash its URL may be missing.
+    pub fn set_directory(&mut self) {
+        self.is_dir = true;
+        if !self.url.path().ends_with('/') {
+            self.url.set_path(&format!("{}/", self.url.path()));
+        }
+    }
+
+    /// Sets the size from a size column, leaving it unset if the column cannot be parsed.
+    fn set_size_column(&mut self, text: &str) {
+        if let Some((size, exact)) = parse_listing_size(text) {
+            self.size = Some(size);
+            self.size_exact = exact;
+        }
+    }
+}
+
+/// Parses the directory listing pages of one kind of web server.
+pub trait IndexParser: Send + Sync {
+    /// Returns true if the `Server` header belongs to this kind of server.
+    fn matches_server(&self, server: &str) -> bool;
+
+    /// Returns true if the page markup looks like it was generated by this kind of server.
+    fn matches_markup(&self, body: &str) -> bool;
+
+    /// Parses the listing page located at `base` into its entries.
+    fn parse(
+        &self,
+        body: &str,
+        base: &Url,
+    ) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Parser for nginx `autoindex` pages.
+pub struct NginxParser;
+
+/// Parser for Apache `mod_autoindex` pages, in both the table and `<pre>` layouts.
+pub struct ApacheParser;
+
+/// Parser for lighttpd `mod_dirlisting` pages.
+pub struct LighttpdParser;
+
+/// Parser for Caddy `browse` pages.
+pub struct CaddyParser;
+
+/// Parser for nginx `autoindex_format json` listings.
+pub struct NginxJsonParser;
+
+/// Parser for nginx `autoindex_format xml` listings.
+pub struct NginxXmlParser;
+
+/// Fallback parser that follows every link, guessing directories from a trailing slash.
+pub struct GenericParser;
+
+/// Parsers tried during auto-detection, in order.
+static DETECTABLE_PARSERS: [&dyn IndexParser; 4] =
+    [&ApacheParser, &LighttpdParser, &CaddyParser, &NginxParser];
+
+/// Returns the parser for the given server flavour.
+///
+/// For [`ServerFlavour::Auto`] the page markup is checked first, since reverse proxies often
+/// rewrite the `Server` header, and the `Server` header second.
+pub fn parser_for(
+    flavour: ServerFlavour,
+    server: Option<&str>,
+    body: &str,
+) -> &'static dyn IndexParser {
+    match flavour {
+        ServerFlavour::Nginx => &NginxParser,
+        ServerFlavour::Apache => &ApacheParser,
+        ServerFlavour::Lighttpd => &LighttpdParser,
+        ServerFlavour::Caddy => &CaddyParser,
+        ServerFlavour::Generic => &GenericParser,
+        ServerFlavour::Auto => DETECTABLE_PARSERS
+            .iter()
+            .find(|parser| parser.matches_markup(body))
+            .or_else(|| {
+                let server = server?;
+                DETECTABLE_PARSERS
+                    .iter()
+                    .find(|parser| parser.matches_server(server))
+            })
+            .copied()
+            .unwrap_or(&GenericParser),
+    }
+}
+
+impl IndexParser for NginxParser {
+    fn matches_server(&self, server: &str) -> bool {
+        server.to_lowercase().starts_with("nginx")
+    }
+
+    fn matches_markup(&self, body: &str) -> bool {
+        body.contains("<hr><pre>")
+    }
+
+    fn parse(
+        &self,
+        body: &str,
+        base: &Url,
+    ) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(parse_pre_listing(&Html::parse_document(body), base))
+    }
+}
+
+impl IndexParser for ApacheParser {
+    fn matches_server(&self, server: &str) -> bool {
+        server.to_lowercase().starts_with("apache")
+    }
+
+    fn matches_markup(&self, body: &str) -> bool {
+        body.contains("?C=N;O=") || body.contains("<address>Apache")
+    }
+
+    fn parse(
+        &self,
+        body: &str,
+        base: &Url,
+    ) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let document = Html::parse_document(body);
+
+        // `IndexOptions HTMLTable` puts every entry in its own table row, otherwise the
+        // listing is a `<pre>` block like nginx.
+        let row_selector = Selector::parse("table tr").unwrap();
+        let cell_selector = Selector::parse("td").unwrap();
+        let link_selector = Selector::parse("a[href]").unwrap();
+
+        let mut entries = Vec::new();
+        let mut has_table = false;
+
+        for row in document.select(&row_selector) {
+            has_table = true;
+
+            let cells: Vec<ElementRef> = row.select(&cell_selector).collect();
+            let Some((index, link)) = cells
+                .iter()
+                .enumerate()
+                .find_map(|(i, cell)| Some((i, cell.select(&link_selector).next()?)))
+            else {
+                continue;
+            };
+
+            let Some(mut entry) = child_entry(base, link.value().attr("href").unwrap_or("")) else {
+                continue;
+            };
+
+            entry.mtime = cells
+                .get(index + 1)
+                .and_then(|cell| parse_listing_time(&element_text(cell)));
+            if let Some(cell) = cells.get(index + 2) {
+                entry.set_size_column(&element_text(cell));
+            }
+
+            entries.push(entry);
+        }
+
+        if has_table {
+            Ok(entries)
+        } else {
+            Ok(parse_pre_listing(&document, base))
+        }
+    }
+}
+
+impl IndexParser for LighttpdParser {
+    fn matches_server(&self, server: &str) -> bool {
+        server.to_lowercase().starts_with("lighttpd")
+    }
+
+    fn matches_markup(&self, body: &str) -> bool {
+        body.contains("summary=\"Directory Listing\"")
+            || body.contains("<div class=\"foot\">lighttpd")
+    }
+
+    fn parse(
+        &self,
+        body: &str,
+        base: &Url,
+    ) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let document = Html::parse_document(body);
+        let row_selector = Selector::parse("tr").unwrap();
+        let link_selector = Selector::parse("td.n a[href]").unwrap();
+        let mtime_selector = Selector::parse("td.m").unwrap();
+        let size_selector = Selector::parse("td.s").unwrap();
+
+        let mut entries = Vec::new();
+
+        for row in document.select(&row_selector) {
+            let Some(link) = row.select(&link_selector).next() else {
+                continue;
+            };
+
+            let Some(mut entry) = child_entry(base, link.value().attr("href").unwrap_or("")) else {
+                continue;
+            };
+
+            // Directory rows have the `d` class, even if the link lacks a trailing slash.
+            if !entry.is_dir
+                && row
+                    .value()
+                    .has_class("d", scraper::CaseSensitivity::CaseSensitive)
+            {
+                entry.set_directory();
+            }
+            entry.mtime = row
+                .select(&mtime_selector)
+                .next()
+                .and_then(|cell| parse_listing_time(&element_text(&cell)));
+            if let Some(cell) = row.select(&size_selector).next() {
+                entry.set_size_column(&element_text(&cell));
+            }
+
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+}
+
+impl IndexParser for CaddyParser {
+    fn matches_server(&self, server: &str) -> bool {
+        server.to_lowercase().starts_with("caddy")
+    }
+
+    fn matches_markup(&self, body: &str) -> bool {
+        body.contains("caddyserver.com")
+    }
+
+    fn parse(
+        &self,
+        body: &str,
+        base: &Url,
+    ) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let document = Html::parse_document(body);
+        let row_selector = Selector::parse("tr").unwrap();
+        let link_selector = Selector::parse("a[href]").unwrap();
+        let size_selector = Selector::parse("td[data-size], td[data-order]").unwrap();
+        let time_selector = Selector::parse("time[datetime]").unwrap();
+
+        let mut entries = Vec::new();
+
+        for row in document.select(&row_selector) {
+            let Some(link) = row.select(&link_selector).next() else {
+                continue;
+            };
+
+            let Some(mut entry) = child_entry(base, link.value().attr("href").unwrap_or("")) else {
+                continue;
+            };
+
+            // Directories have a negative sort order in place of a size.
+            entry.size = row.select(&size_selector).next().and_then(|cell| {
+                let value = cell.value();
+                value
+                    .attr("data-size")
+                    .or_else(|| value.attr("data-order"))?
+                    .parse()
+                    .ok()
+            });
+            entry.size_exact = entry.size.is_some();
+            entry.mtime = row
+                .select(&time_selector)
+                .next()
+                .and_then(|time| time.value().attr("datetime"))
+                .and_then(|datetime| DateTime::parse_from_rfc3339(datetime).ok())
+                .map(|datetime| datetime.with_timezone(&Utc));
+            entry.mtime_exact = entry.mtime.is_some();
+
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+}
+
+/// An entry of an nginx `autoindex_format json` listing.
+#[derive(Debug, Deserialize)]
+struct NginxJsonEntry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    mtime: Option<String>,
+    size: Option<u64>,
+}
+
+impl IndexParser for NginxJsonParser {
+    fn matches_server(&self, server: &str) -> bool {
+        NginxParser.matches_server(server)
+    }
+
+    fn matches_markup(&self, body: &str) -> bool {
+        body.trim_start().starts_with('[')
+    }
+
+    fn parse(
+        &self,
+        body: &str,
+        base: &Url,
+    ) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let listing: Vec<NginxJsonEntry> = serde_json::from_str(body)?;
+
+        Ok(listing
+            .into_iter()
+            .filter_map(|item| {
+                let mut entry = named_entry(base, &item.name, item.kind == "directory")?;
+                entry.size = item.size;
+                entry.size_exact = item.size.is_some();
+                entry.mtime = item
+                    .mtime
+                    .and_then(|mtime| DateTime::parse_from_rfc2822(&mtime).ok())
+                    .map(|mtime| mtime.with_timezone(&Utc));
+                entry.mtime_exact = entry.mtime.is_some();
+                Some(entry)
+            })
+            .collect())
+    }
+}
+
+impl IndexParser for NginxXmlParser {
+    fn matches_server(&self, server: &str) -> bool {
+        NginxParser.matches_server(server)
+    }
+
+    fn matches_markup(&self, body: &str) -> bool {
+        body.contains("<list>")
+    }
+
+    fn parse(
+        &self,
+        body: &str,
+        base: &Url,
+    ) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let list = XmlElement::parse(body)?;
+
+        Ok(list
+            .children
+            .iter()
+            .filter_map(|item| {
+                let mut entry = named_entry(base, &item.text, item.name == "directory")?;
+                entry.size = item.attr("size").and_then(|size| size.parse().ok());
+                entry.size_exact = entry.size.is_some();
+                entry.mtime = item
+                    .attr("mtime")
+                    .and_then(|mtime| DateTime::parse_from_rfc3339(mtime).ok())
+                    .map(|mtime| mtime.with_timezone(&Utc));
+                entry.mtime_exact = entry.mtime.is_some();
+                Some(entry)
+            })
+            .collect())
+    }
+}
+
+impl IndexParser for GenericParser {
+    fn matches_server(&self, _server: &str) -> bool {
+        false
+    }
+
+    fn matches_markup(&self, _body: &str) -> bool {
+        false
+    }
+
+    fn parse(
+        &self,
+        body: &str,
+        base: &Url,
+    ) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let document = Html::parse_document(body);
+        let selector = Selector::parse("a[href]").unwrap();
+
+        Ok(document
+            .select(&selector)
+            .filter_map(|link| child_entry(base, link.value().attr("href")?))
+            .collect())
+    }
+}
+
+/// Parses a `<pre>` listing where each link is followed by a text node holding the
+/// modification date, time and size columns, as produced by nginx and Apache.
+fn parse_pre_listing(document: &Html, base: &Url) -> Vec<ListingEntry> {
+    let selector = Selector::parse("pre a[href]").unwrap();
+
+    let mut entries = Vec::new();
+
+    for link in document.select(&selector) {
+        let Some(mut entry) = child_entry(base, link.value().attr("href").unwrap_or("")) else {
+            continue;
+        };
+
+        if let Some(columns) = link.next_sibling().and_then(|node| node.value().as_text()) {
+            let columns: Vec<&str> = columns.split_whitespace().collect();
+            if columns.len() >= 2 {
+                entry.mtime = parse_listing_time(&format!("{} {}", columns[0], columns[1]));
+            }
+            if let Some(size) = columns.get(2) {
+                entry.set_size_column(size);
+            }
+        }
+
+        entries.push(entry);
+    }
+
+    entries
+}
+
+/// Resolves `href` against `base` and builds an entry for it, if it points to a direct child
+/// of the listed directory.
+///
+/// The names are decoded when the files are saved, so names that only turn into a path or a
+/// `.`/`..` segment once decoded (like `a%2F..%2F..%2Fx`) are rejected as well.
+pub fn child_entry(base: &Url, href: &str) -> Option<ListingEntry> {
+    if should_skip_url(href) {
+        return None;
+    }
+
+    let directory = base.join("./").ok()?;
+    let mut url = directory.join(href).ok()?;
+    url.set_fragment(None);
+
+    if url.origin() != directory.origin() || url.query().is_some() {
+        return None;
+    }
+
+    let relative = url.path().strip_prefix(directory.path())?;
+    let is_dir = relative.ends_with('/');
+    let name = relative.trim_end_matches('/');
+
+    let decoded = percent_decode_str(name).decode_utf8().ok()?;
+    if name.is_empty() || decoded.contains('/') || decoded == "." || decoded == ".." {
+        return None;
+    }
+
+    Some(ListingEntry {
+        name: name.to_string(),
+        is_dir,
+        url,
+        size: None,
+        size_exact: false,
+        mtime: None,
+        mtime_exact: false,
+        etag: None,
+    })
+}
+
+/// Builds an entry from a plain (not percent-encoded) file or directory name.
+pub fn named_entry(base: &Url, name: &str, is_dir: bool) -> Option<ListingEntry> {
+    let mut href = encode_path(name);
+    if is_dir {
+        href.push('/');
+    }
+
+    // Prefix with `./` so names containing a colon aren't mistaken for a URL scheme
+    child_entry(base, &format!("./{}", href))
+}
+
+/// Collects the trimmed text content of an element.
+fn element_text(element: &ElementRef) -> String {
+    element.text().collect::<String>().trim().to_string()
+}
+
+/// Parses a modification time column, which every server formats slightly differently.
+///
+/// The columns don't show a time zone, and Apache and lighttpd print the server's local time,
+/// so the time is read as UTC but can be off by the server's offset. Entries with such a time
+/// leave `mtime_exact` unset.
+pub fn parse_listing_time(text: &str) -> Option<DateTime<Utc>> {
+    const FORMATS: [&str; 5] = [
+        "%d-%b-%Y %H:%M",    // nginx, older Apache
+        "%Y-%m-%d %H:%M",    // Apache
+        "%Y-%m-%d %H:%M:%S", // Apache with seconds
+        "%Y-%b-%d %H:%M:%S", // lighttpd
+        "%d-%b-%Y %H:%M:%S", // nginx with seconds
+    ];
+
+    let text = text.trim();
+
+    FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .map(|time| time.and_utc())
+}
+
+/// Parses a size column such as `1234`, `1.2K` or `15 MiB` into the size in bytes and whether
+/// it is exact. Returns `None` for directories, which are shown as `-`.
+pub fn parse_listing_size(text: &str) -> Option<(u64, bool)> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let number: f64 = number.parse().ok()?;
+    let multiplier: u64 = match unit
+        .trim()
+        .to_uppercase()
+        .trim_end_matches("IB")
+        .trim_end_matches('B')
+    {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        "T" => 1024 * 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    Some(((number * multiplier as f64) as u64, multiplier == 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://example.com/pub/").unwrap()
+    }
+
+    fn time(text: &str) -> Option<DateTime<Utc>> {
+        Some(
+            DateTime::parse_from_rfc3339(text)
+                .unwrap()
+                .with_timezone(&Utc),
+        )
+    }
+
+    /// Parses the page with the parser detected from its markup.
+    fn parse(body: &str) -> Vec<ListingEntry> {
+        parser_for(ServerFlavour::Auto, None, body)
+            .parse(body, &base())
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_nginx_listing() {
+        let body = r#"<html>
+<head><title>Index of /pub/</title></head>
+<body>
+<h1>Index of /pub/</h1><hr><pre><a href="../">../</a>
+<a href="docs/">docs/</a>                                              15-Nov-2024 10:20                   -
+<a href="release%201.0.tar.gz">release 1.0.tar.gz</a>                                 15-Nov-2024 10:21             1048576
+</pre><hr></body>
+</html>
+"#;
+
+        let entries = parse(body);
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].name, "docs");
+        assert_eq!(entries[0].url.as_str(), "https://example.com/pub/docs/");
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].size, None);
+        assert_eq!(entries[0].mtime, time("2024-11-15T10:20:00Z"));
+
+        assert_eq!(entries[1].name, "release%201.0.tar.gz");
+        assert!(!entries[1].is_dir);
+        assert_eq!(entries[1].size, Some(1048576));
+        assert!(entries[1].size_exact);
+        assert_eq!(entries[1].mtime, time("2024-11-15T10:21:00Z"));
+        assert!(!entries[1].mtime_exact);
+    }
+
+    #[test]
+    fn parses_apache_table_listing() {
+        let body = r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 3.2 Final//EN">
+<html>
+ <head>
+  <title>Index of /pub</title>
+ </head>
+ <body>
+<h1>Index of /pub</h1>
+  <table>
+   <tr><th valign="top"><img src="/icons/blank.gif" alt="[ICO]"></th><th><a href="?C=N;O=D">Name</a></th><th><a href="?C=M;O=A">Last modified</a></th><th><a href="?C=S;O=A">Size</a></th><th><a href="?C=D;O=A">Description</a></th></tr>
+   <tr><th colspan="5"><hr></th></tr>
+<tr><td valign="top"><img src="/icons/back.gif" alt="[PARENTDIR]"></td><td><a href="/">Parent Directory</a></td><td>&nbsp;</td><td align="right">  - </td><td>&nbsp;</td></tr>
+<tr><td valign="top"><img src="/icons/folder.gif" alt="[DIR]"></td><td><a href="docs/">docs/</a></td><td align="right">2024-11-15 10:20  </td><td align="right">  - </td><td>&nbsp;</td></tr>
+<tr><td valign="top"><img src="/icons/compressed.gif" alt="[   ]"></td><td><a href="archive.zip">archive.zip</a></td><td align="right">2024-11-15 10:21  </td><td align="right">1.2M</td><td>&nbsp;</td></tr>
+   <tr><th colspan="5"><hr></th></tr>
+</table>
+<address>Apache/2.4.62 (Debian) Server at example.com Port 80</address>
+</body></html>
+"#;
+
+        let entries = parse(body);
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].name, "docs");
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].size, None);
+        assert_eq!(entries[0].mtime, time("2024-11-15T10:20:00Z"));
+
+        assert_eq!(entries[1].name, "archive.zip");
+        assert!(!entries[1].is_dir);
+        assert_eq!(entries[1].size, Some(1258291));
+        assert!(!entries[1].size_exact);
+        assert_eq!(entries[1].mtime, time("2024-11-15T10:21:00Z"));
+        assert!(!entries[1].mtime_exact);
+    }
+
+    #[test]
+    fn parses_apache_pre_listing() {
+        let body = r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 3.2 Final//EN">
+<html>
+ <head>
+  <title>Index of /pub</title>
+ </head>
+ <body>
+<h1>Index of /pub</h1>
+<pre><img src="/icons/blank.gif" alt="Icon "> <a href="?C=N;O=D">Name</a>                    <a href="?C=M;O=A">Last modified</a>      <a href="?C=S;O=A">Size</a>  <a href="?C=D;O=A">Description</a><hr><img src="/icons/back.gif" alt="[PARENTDIR]"> <a href="/">Parent Directory</a>                             -   
+<img src="/icons/folder.gif" alt="[DIR]"> <a href="docs/">docs/</a>                   2024-11-15 10:20    -   
+<img src="/icons/compressed.gif" alt="[   ]"> <a href="archive.zip">archive.zip</a>             2024-11-15 10:21  1.2M  
+<hr></pre>
+<address>Apache/2.4.62 (Debian) Server at example.com Port 80</address>
+</body></html>
+"#;
+
+        let entries = parse(body);
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].name, "docs");
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].size, None);
+        assert_eq!(entries[0].mtime, time("2024-11-15T10:20:00Z"));
+
+        assert_eq!(entries[1].name, "archive.zip");
+        assert_eq!(entries[1].size, Some(1258291));
+        assert!(!entries[1].size_exact);
+        assert_eq!(entries[1].mtime, time("2024-11-15T10:21:00Z"));
+    }
+
+    #[test]
+    fn parses_lighttpd_listing() {
+        let body = r#"<!DOCTYPE html>
+<html>
+<head><title>Index of /pub/</title></head>
+<body>
+<h2>Index of /pub/</h2>
+<div class="list">
+<table summary="Directory Listing" cellpadding="0" cellspacing="0">
+<thead><tr><th class="n">Name</th><th class="m">Last Modified</th><th class="s">Size</th><th class="t">Type</th></tr></thead>
+<tbody>
+<tr class="d"><td class="n"><a href="../">..</a>/</td><td class="m">&nbsp;</td><td class="s">- &nbsp;</td><td class="t">Directory</td></tr>
+<tr class="d"><td class="n"><a href="docs">docs</a>/</td><td class="m">2024-Nov-15 10:20:00</td><td class="s">- &nbsp;</td><td class="t">Directory</td></tr>
+<tr><td class="n"><a href="notes.txt">notes.txt</a></td><td class="m">2024-Nov-15 10:21:30</td><td class="s">3.4K</td><td class="t">text/plain</td></tr>
+</tbody>
+</table>
+</div>
+<div class="foot">lighttpd/1.4.76</div>
+</body>
+</html>
+"#;
+
+        let entries = parse(body);
+        assert_eq!(entries.len(), 2);
+
+        // The row class marks the directory, although its link has no trailing slash
+        assert_eq!(entries[0].name, "docs");
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].url.as_str(), "https://example.com/pub/docs/");
+        assert_eq!(entries[0].size, None);
+        assert_eq!(entries[0].mtime, time("2024-11-15T10:20:00Z"));
+
+        assert_eq!(entries[1].name, "notes.txt");
+        assert!(!entries[1].is_dir);
+        assert_eq!(entries[1].size, Some(3481));
+        assert!(!entries[1].size_exact);
+        assert_eq!(entries[1].mtime, time("2024-11-15T10:21:30Z"));
+    }
+
+    #[test]
+    fn parses_caddy_listing() {
+        let body = r#"<!DOCTYPE html>
+<html>
+<head><title>/pub/</title></head>
+<body>
+<main>
+<table>
+<thead>
+<tr>
+  <th></th>
+  <th><a href="?sort=name&order=desc">Name</a></th>
+  <th><a href="?sort=size&order=asc">Size</a></th>
+  <th><a href="?sort=time&order=asc">Modified</a></th>
+</tr>
+</thead>
+<tbody>
+<tr>
+  <td></td>
+  <td><a href=".."><span class="go-up">Up</span></a></td>
+  <td>&mdash;</td>
+  <td>&mdash;</td>
+</tr>
+<tr class="file">
+  <td></td>
+  <td><a href="./docs/"><span class="name">docs</span></a></td>
+  <td data-order="-1">&mdash;</td>
+  <td class="timestamp"><time datetime="2024-11-15T10:20:00Z">11/15/2024 10:20:00 AM +00:00</time></td>
+</tr>
+<tr class="file">
+  <td></td>
+  <td><a href="./video.mp4"><span class="name">video.mp4</span></a></td>
+  <td data-size="52428800">50 MiB</td>
+  <td class="timestamp"><time datetime="2024-11-15T10:21:00+02:00">11/15/2024 10:21:00 AM +02:00</time></td>
+</tr>
+</tbody>
+</table>
+</main>
+<footer>Served with <a rel="noopener noreferrer" href="https://caddyserver.com">Caddy</a></footer>
+</body>
+</html>
+"#;
+
+        let entries = parse(body);
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].name, "docs");
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].size, None);
+        assert_eq!(entries[0].mtime, time("2024-11-15T10:20:00Z"));
+
+        assert_eq!(entries[1].name, "video.mp4");
+        assert!(!entries[1].is_dir);
+        assert_eq!(entries[1].size, Some(52428800));
+        assert!(entries[1].size_exact);
+        assert_eq!(entries[1].mtime, time("2024-11-15T08:21:00Z"));
+        assert!(entries[1].mtime_exact);
+    }
+
+    #[test]
+    fn parses_nginx_json_listing() {
+        let body = r#"[
+{ "name":"docs", "type":"directory", "mtime":"Fri, 15 Nov 2024 10:20:00 GMT" },
+{ "name":"a b.txt", "type":"file", "mtime":"Fri, 15 Nov 2024 10:21:00 GMT", "size":1234 },
+{ "name":"report:2024.pdf", "type":"file", "mtime":"Fri, 15 Nov 2024 10:22:00 GMT", "size":0 }
+]
+"#;
+
+        let entries = NginxJsonParser.parse(body, &base()).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0].name, "docs");
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].url.as_str(), "https://example.com/pub/docs/");
+        assert_eq!(entries[0].size, None);
+        assert_eq!(entries[0].mtime, time("2024-11-15T10:20:00Z"));
+
+        assert_eq!(entries[1].name, "a%20b.txt");
+        assert!(!entries[1].is_dir);
+        assert_eq!(entries[1].url.as_str(), "https://example.com/pub/a%20b.txt");
+        assert_eq!(entries[1].size, Some(1234));
+        assert!(entries[1].size_exact);
+        assert_eq!(entries[1].mtime, time("2024-11-15T10:21:00Z"));
+        assert!(entries[1].mtime_exact);
+
+        // A colon in the name mustn't be taken for a URL scheme
+        assert_eq!(
+            entries[2].url.as_str(),
+            "https://example.com/pub/report:2024.pdf"
+        );
+        assert_eq!(entries[2].size, Some(0));
+
+        assert!(NginxJsonParser.parse("<html></html>", &base()).is_err());
+    }
+
+    #[test]
+    fn parses_nginx_xml_listing() {
+        let body = r#"<?xml version="1.0"?>
+<list>
+<directory mtime="2024-11-15T10:20:00Z">docs</directory>
+<file mtime="2024-11-15T10:21:00Z" size="1234">a b.txt</file>
+<file mtime="2024-11-15T10:22:00Z" size="7">R&amp;D.txt</file>
+</list>
+"#;
+
+        let entries = NginxXmlParser.parse(body, &base()).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0].name, "docs");
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].size, None);
+        assert_eq!(entries[0].mtime, time("2024-11-15T10:20:00Z"));
+
+        assert_eq!(entries[1].name, "a%20b.txt");
+        assert!(!entries[1].is_dir);
+        assert_eq!(entries[1].size, Some(1234));
+        assert!(entries[1].size_exact);
+        assert_eq!(entries[1].mtime, time("2024-11-15T10:21:00Z"));
+        assert!(entries[1].mtime_exact);
+
+        assert_eq!(
+            percent_encoding::percent_decode_str(&entries[2].name).decode_utf8_lossy(),
+            "R&D.txt"
+        );
+        assert_eq!(entries[2].size, Some(7));
+    }
+
+    #[test]
+    fn parses_pre_listing_columns() {
+        let body = r#"<html><body>
+<a href="outside.txt">outside.txt</a>
+<pre><a href="bare.txt">bare.txt</a>
+<a href="dated.txt">dated.txt</a>   15-Nov-2024 10:20:30
+<a href="sized.txt">sized.txt</a>   15-Nov-2024 10:20   15M
+<a href="odd.txt">odd.txt</a>   yesterday
+</pre></body></html>"#;
+
+        let entries = parse_pre_listing(&Html::parse_document(body), &base());
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["bare.txt", "dated.txt", "sized.txt", "odd.txt"]);
+
+        assert_eq!(entries[0].mtime, None);
+        assert_eq!(entries[0].size, None);
+
+        assert_eq!(entries[1].mtime, time("2024-11-15T10:20:30Z"));
+        assert_eq!(entries[1].size, None);
+
+        assert_eq!(entries[2].mtime, time("2024-11-15T10:20:00Z"));
+        assert_eq!(entries[2].size, Some(15 * 1024 * 1024));
+        assert!(!entries[2].size_exact);
+
+        assert_eq!(entries[3].mtime, None);
+        assert_eq!(entries[3].size, None);
+    }
+
+    #[test]
+    fn builds_child_entries() {
+        let base = Url::parse("https://example.com/pub/index.html").unwrap();
+
+        let entry = child_entry(&base, "docs/").unwrap();
+        assert_eq!(entry.name, "docs");
+        assert!(entry.is_dir);
+        assert_eq!(entry.url.as_str(), "https://example.com/pub/docs/");
+
+        let entry = child_entry(&base, "/pub/a%20b.txt#top").unwrap();
+        assert_eq!(entry.name, "a%20b.txt");
+        assert!(!entry.is_dir);
+        assert_eq!(entry.url.as_str(), "https://example.com/pub/a%20b.txt");
+
+        let entry = child_entry(&base, "https://example.com/pub/c.txt").unwrap();
+        assert_eq!(entry.name, "c.txt");
+
+        for href in [
+            "../",
+            "#",
+            "?C=N;O=D",
+            "mailto:admin@example.com",
+            "/",
+            "/other/file.txt",
+            "docs/file.txt",
+            "file.txt?download=1",
+            "https://mirror.example.com/pub/file.txt",
+            "a%2F..%2F..%2Fx",
+            "docs%2Ffile.txt",
+            "..%2F",
+            "%2E%2E%2F",
+            "%2E/",
+        ] {
+            assert!(child_entry(&base, href).is_none(), "{}", href);
+        }
+    }
+
+    #[test]
+    fn parses_listing_sizes() {
+        assert_eq!(parse_listing_size("1234"), Some((1234, true)));
+        assert_eq!(parse_listing_size(" 0 "), Some((0, true)));
+        assert_eq!(parse_listing_size("1.2K"), Some((1228, false)));
+        assert_eq!(parse_listing_size("15M"), Some((15 * 1024 * 1024, false)));
+        assert_eq!(
+            parse_listing_size("15 MiB"),
+            Some((15 * 1024 * 1024, false))
+        );
+        assert_eq!(parse_listing_size("2.5 GB"), Some((2684354560, false)));
+        assert_eq!(parse_listing_size("1T"), Some((1 << 40, false)));
+        assert_eq!(parse_listing_size("-"), None);
+        assert_eq!(parse_listing_size(""), None);
+        assert_eq!(parse_listing_size("12 parsecs"), None);
+    }
+
+    #[test]
+    fn parses_listing_times() {
+        let expected = time("2024-11-15T10:20:00Z");
+        assert_eq!(parse_listing_time("15-Nov-2024 10:20"), expected);
+        assert_eq!(parse_listing_time("2024-11-15 10:20"), expected);
+        assert_eq!(parse_listing_time(" 2024-11-15 10:20  "), expected);
+
+        let expected = time("2024-11-15T10:20:30Z");
+        assert_eq!(parse_listing_time("2024-11-15 10:20:30"), expected);
+        assert_eq!(parse_listing_time("2024-Nov-15 10:20:30"), expected);
+        assert_eq!(parse_listing_time("15-Nov-2024 10:20:30"), expected);
+
+        assert_eq!(parse_listing_time("-"), None);
+        assert_eq!(parse_listing_time("yesterday"), None);
+    }
+}
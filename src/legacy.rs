@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use bincode::Options;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::crawl_data::{CrawlData, DownloadData};
+
+/// Crawl data of version 1.0.1, which only recorded the URL and path of each file.
+#[derive(Deserialize)]
+struct BaselineCrawlData {
+    download_list: Vec<BaselineDownloadData>,
+    total_size: u64,
+    directories_to_create: Vec<String>,
+    saved_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct BaselineDownloadData {
+    url: String,
+    output_dir: String,
+}
+
+/// Decodes crawl data written by version 1.0.1, before the file header was added.
+pub fn migrate(data: &[u8]) -> Option<CrawlData> {
+    let old = decode::<BaselineCrawlData>(data)?;
+
+    // The file sizes were only summed up, so they are unknown until the files are checked
+    let download_list = old
+        .download_list
+        .into_iter()
+        .map(|file| DownloadData {
+            url: file.url,
+            output_dir: file.output_dir,
+            size: None,
+            size_exact: false,
+            modified: None,
+            modified_exact: false,
+            etag: None,
+        })
+        .collect();
+    // Each directory was recorded with its name repeated (`a/b/b` for `a/b`)
+    let directories_to_create = old
+        .directories_to_create
+        .iter()
+        .filter_map(|directory| directory.trim_end_matches('/').rsplit_once('/'))
+        .map(|(directory, _)| directory.to_string())
+        .collect();
+    let mut crawl_data = CrawlData {
+        download_list,
+        total_size: old.total_size,
+        directories_to_create,
+        saved_at: old.saved_at,
+        failures: Vec::new(),
+        listings: HashMap::new(),
+        failed_directories: Vec::new(),
+    };
+
+    normalize_paths(&mut crawl_data);
+    strip_output_dir(&mut crawl_data.directories_to_create);
+
+    Some(crawl_data)
+}
+
+/// Decodes the data with the layout, only if it fits the data exactly, as the file has no
+/// header to recognise it by.
+fn decode<T: DeserializeOwned>(data: &[u8]) -> Option<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(data)
+        .ok()
+}
+
+/// Older crawls prefixed files in the crawl root with a slash and joined directory names that
+/// end in a slash with another one, so the paths are cleaned up to match newer crawls.
+fn normalize_paths(crawl_data: &mut CrawlData) {
+    let normalize = |path: &str| {
+        path.split('/')
+            .enumerate()
+            .filter(|(i, segment)| *i == 0 || !segment.is_empty())
+            .map(|(_, segment)| segment)
+            .collect::<Vec<_>>()
+            .join("/")
+    };
+
+    for file in &mut crawl_data.download_list {
+        file.output_dir = normalize(file.output_dir.trim_start_matches('/'));
+    }
+    for directory in &mut crawl_data.directories_to_create {
+        *directory = normalize(directory.trim_end_matches('/'));
+    }
+}
+
+/// Older crawls recorded the directories with the output directory of the crawl in front. It
+/// isn't saved anywhere else, so it is taken from the shallowest directory, which is directly in
+/// the output directory.
+fn strip_output_dir(directories: &mut [String]) {
+    let Some((output_dir, _)) = directories
+        .iter()
+        .min_by_key(|directory| directory.matches('/').count())
+        .and_then(|directory| directory.rsplit_once('/'))
+    else {
+        return;
+    };
+    let prefix = format!("{}/", output_dir);
+
+    for directory in directories {
+        if let Some(relative) = directory.strip_prefix(&prefix) {
+            *directory = relative.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct WrittenCrawlData<'a> {
+        download_list: Vec<WrittenDownloadData<'a>>,
+        total_size: u64,
+        directories_to_create: Vec<&'a str>,
+        saved_at: DateTime<Utc>,
+    }
+
+    #[derive(Serialize)]
+    struct WrittenDownloadData<'a> {
+        url: &'a str,
+        output_dir: &'a str,
+    }
+
+    /// Encodes crawl data the way version 1.0.1 wrote it, for a crawl to `output_dir`.
+    fn written_by_1_0_1(output_dir: &str) -> Vec<u8> {
+        let docs = format!("{}/docs//docs/", output_dir);
+        let guides = format!("{}/docs//guides//guides/", output_dir);
+        let crawl_data = WrittenCrawlData {
+            download_list: vec![
+                WrittenDownloadData {
+                    url: "https://example.com/pub/top.txt",
+                    output_dir: "/top.txt",
+                },
+                WrittenDownloadData {
+                    url: "https://example.com/pub/docs/a%20b.txt",
+                    output_dir: "docs//a%20b.txt",
+                },
+                WrittenDownloadData {
+                    url: "https://example.com/pub/docs/guides/c.txt",
+                    output_dir: "docs//guides//c.txt",
+                },
+            ],
+            total_size: 1234,
+            directories_to_create: vec![&docs, &guides],
+            saved_at: DateTime::parse_from_rfc3339("2024-11-15T10:20:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+        };
+
+        bincode::serialize(&crawl_data).unwrap()
+    }
+
+    #[test]
+    fn migrates_1_0_1_crawl_data() {
+        for output_dir in ["./output", "output", "/data/mirror"] {
+            let crawl_data = migrate(&written_by_1_0_1(output_dir)).unwrap();
+
+            let files: Vec<_> = crawl_data
+                .download_list
+                .iter()
+                .map(|file| (file.url.as_str(), file.output_dir.as_str()))
+                .collect();
+            assert_eq!(
+                files,
+                [
+                    ("https://example.com/pub/top.txt", "top.txt"),
+                    ("https://example.com/pub/docs/a%20b.txt", "docs/a%20b.txt"),
+                    (
+                        "https://example.com/pub/docs/guides/c.txt",
+                        "docs/guides/c.txt"
+                    ),
+                ]
+            );
+            assert!(crawl_data
+                .download_list
+                .iter()
+                .all(|file| file.size.is_none() && !file.size_exact));
+
+            assert_eq!(
+                crawl_data.directories_to_create,
+                ["docs", "docs/guides"],
+                "{}",
+                output_dir
+            );
+            assert_eq!(crawl_data.total_size, 1234);
+            assert_eq!(
+                crawl_data.saved_at.to_rfc3339(),
+                "2024-11-15T10:20:00+00:00"
+            );
+            assert!(crawl_data.failures.is_empty());
+            assert!(crawl_data.listings.is_empty());
+        }
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        assert!(migrate(b"").is_none());
+        assert!(migrate(b"not crawl data at all").is_none());
+
+        // Trailing bytes mean the file was written in another layout
+        let mut data = written_by_1_0_1("./output");
+        data.push(0);
+        assert!(migrate(&data).is_none());
+    }
+}
@@ -1,61 +1,282 @@
+mod checkpoint;
+mod checksum;
 mod config;
 mod crawl_data;
+mod diff;
+mod export;
+mod import;
+mod index_parser;
+mod legacy;
 mod network;
+mod s3;
+mod summary;
+mod sync;
 mod utils;
+mod verify;
+mod webdav;
+mod xml;
 
 use std::{
-    fs::create_dir_all,
-    io::{self},
+    collections::HashMap,
+    fs::{create_dir_all, File},
+    future::Future,
+    io::{self, BufWriter, Write},
     path::Path,
-    process,
-    sync::{atomic::AtomicU64, Arc},
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use chrono::Utc;
-use clap::Parser;
-use config::{Config, DEFAULT_CONFIG_PATH};
+use checkpoint::CrawlCheckpoint;
+use checksum::ChecksumIndex;
+use clap::{
+    error::ErrorKind, parser::ValueSource, CommandFactory, FromArgMatches, Parser, Subcommand,
+};
+use config::{Config, ConfigArgs};
 use crawl_data::CrawlData;
-use indicatif::{ProgressBar, ProgressStyle};
-use network::{crawl_directory, download_files_parallel};
+use diff::diff_crawl_data;
+use export::{export, ExportFormat};
+use import::{import_urls, ImportFormat};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use network::{download_files_parallel, Crawler, DownloadOptions, ListingSource};
 use percent_encoding::percent_decode_str;
-use tokio::{io::AsyncWriteExt, task};
-use tracing::{error, info, trace, warn};
-use utils::{create_http_client, display_prompt};
+use reqwest::{Client, Url};
+use summary::{ExitStatus, RunError, RunSummary};
+use sync::{apply_sync, plan_sync};
+use tokio::task;
+use tracing::{debug, error, info, info_span, trace, warn, Instrument, Span};
+use utils::{create_http_client, display_prompt, exit, format_size};
+use verify::verify_mirror;
 
 /// Command-line arguments
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Path to the saved crawl data file
-    #[arg(short, long, default_value = "crawl_data.bin")]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the crawl data file
+    #[arg(short, long, default_value = "crawl_data.bin", global = true)]
     crawl_data_path: String,
 
-    /// Load crawl data from file instead of crawling the website
-    #[arg(short, long)]
-    load_from_file: bool,
+    /// Options of the download command, which runs when no command is given
+    #[command(flatten)]
+    download: DownloadArgs,
+
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Crawl the website and save the crawl data, without downloading anything
+    Crawl(CrawlArgs),
+    /// Download the files of the website, from a fresh crawl or the saved crawl data. This is
+    /// the default when no command is given
+    Download(DownloadArgs),
+    /// Print the directories and files in the crawl data
+    List {
+        /// File to write the list to, instead of standard output
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Check the output directory against the crawl data without downloading anything, and
+    /// print a JSON report of missing, wrong-size and extra files
+    Verify,
+    /// Compare two crawl data files and print the files that were added, removed or changed
+    /// size between them
+    Diff {
+        /// The older crawl data file
+        old: String,
+        /// The newer crawl data file
+        new: String,
+        /// Print the differences as JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Export the list of files in the crawl data, with their URL, path, size and modification
+    /// time
+    Export {
+        /// Format of the exported list
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// File to write the list to, instead of standard output
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Directory aria2 places the files in
+        #[arg(long, default_value = ".")]
+        dir: String,
+    },
+    /// Write a configuration file with the default settings, replaced by any given as options
+    /// or environment variables
+    Init {
+        /// Replace the configuration file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Build crawl data from a list of URLs, so they can be downloaded without crawling
+    Import {
+        /// Text file with one URL per line, or CSV file with a `url` column
+        input: String,
+        /// URL the output paths are relative to, instead of the URL from the configuration
+        #[arg(long)]
+        base: Option<String>,
+        /// Format of the list, detected from the file extension by default
+        #[arg(long, value_enum)]
+        format: Option<ImportFormat>,
+    },
+}
+
+/// Options for crawling the website.
+#[derive(clap::Args, Debug)]
+struct CrawlArgs {
+    /// Resume an interrupted crawl from its checkpoint file
+    #[arg(long, alias = "resume-crawl")]
+    resume: bool,
 
-    /// Save crawl data to file after crawling the website
+    /// List every directory in full, instead of reusing the unchanged listings of the saved
+    /// crawl data
+    #[arg(long)]
+    full: bool,
+
+    /// Run for every `[site.<name>]` table of the configuration file, each with its own crawl
+    /// data file
+    #[arg(long, conflicts_with = "site")]
+    all_sites: bool,
+
+    /// With --all-sites, run the sites at the same time instead of one after another
+    #[arg(long, requires = "all_sites")]
+    concurrent_sites: bool,
+}
+
+/// Options for downloading the files.
+#[derive(clap::Args, Debug)]
+struct DownloadArgs {
+    /// Download the files in the saved crawl data instead of crawling the website
+    #[arg(short, long, conflicts_with_all = ["save", "resume", "full"])]
+    load: bool,
+
+    /// Save the crawl data after crawling the website
     #[arg(short, long)]
-    save_to_file: bool,
+    save: bool,
+
+    #[command(flatten)]
+    crawl: CrawlArgs,
 
     /// Always answer "yes" to all prompts
     #[arg(short, long)]
     yes: bool,
 
-    /// Only scan the files and then exit
+    /// Path to the file the failed downloads are written to
+    #[arg(long, default_value = "failures.bin")]
+    failures_path: String,
+
+    /// Only download the files that failed in an earlier run, as listed in the failures file
+    #[arg(long, conflicts_with_all = ["load", "save", "resume", "full"])]
+    retry_failed: bool,
+
+    /// Write a JSON summary of the run to this file
     #[arg(long)]
-    scan_only: bool,
+    report: Option<String>,
 
-    /// Read the crawl data and output the list of files to download as a text file
-    #[arg(short, long)]
-    read: bool,
+    /// Delete local files and directories that are no longer in the crawl data. Files the
+    /// filters exclude are kept, as they weren't crawled
+    #[arg(long, conflicts_with = "retry_failed")]
+    delete: bool,
+
+    /// With --delete, only list what would be deleted, without deleting or downloading anything
+    #[arg(long, requires = "delete")]
+    dry_run: bool,
+
+    /// With --delete, abort instead of deleting more than this many files
+    #[arg(long, default_value_t = 1000)]
+    max_delete: usize,
+
+    /// With --delete, move the deleted files to this directory instead of removing them
+    #[arg(long, requires = "delete")]
+    trash_dir: Option<String>,
+}
+
+/// Parses the command-line arguments.
+///
+/// The global options can be given before or after the command, but the download options
+/// before a command belong to the default download command, so they can't be combined with
+/// another one.
+fn parse_args() -> Args {
+    let mut command = Args::command();
+    let matches = command.get_matches_mut();
+
+    if let Some((name, _)) = matches.subcommand() {
+        let download = <DownloadArgs as clap::Args>::augment_args(clap::Command::new("download"));
+        let misplaced = download.get_arguments().find(|arg| {
+            matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+        });
+        if let Some(arg) = misplaced {
+            command
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "--{} is an option of the download command and can't be used with the {} command",
+                        arg.get_long().unwrap_or(arg.get_id().as_str()),
+                        name
+                    ),
+                )
+                .exit();
+        }
+    }
+
+    Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+}
+
+/// A website to crawl, with its settings.
+struct Site {
+    /// Name of its `[site.<name>]` table, or None for the global settings.
+    name: Option<String>,
+    config: Config,
+}
+
+impl Site {
+    /// Returns the site's own copy of a file path.
+    fn path(&self, path: &str) -> String {
+        site_path(path, self.name.as_deref())
+    }
+}
+
+/// Adds the name of the site to a file path, before the extension (`crawl_data.bin` becomes
+/// `crawl_data.<site>.bin`), so each site keeps its own files.
+fn site_path(path: &str, site: Option<&str>) -> String {
+    let Some(site) = site else {
+        return path.to_string();
+    };
+
+    let path = Path::new(path);
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{}", site));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Writes the report of a run that ended early, if one was requested, and exits.
+fn abort(report: Option<&str>, status: ExitStatus, error: String, started: Instant) -> ! {
+    if let Some(report_path) = report {
+        let summary = RunSummary::aborted(status, error, started.elapsed());
+        if let Err(e) = summary.write(report_path) {
+            error!("Failed to write the report to {}: {}", report_path, e);
+        }
+    }
+    exit(status)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Parse the command-line arguments
-    let args = Args::parse();
+    let args = parse_args();
+    let started = Instant::now();
 
     // Initialize the logger.
 
@@ -63,203 +284,273 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     #[cfg(debug_assertions)]
     let subscriber = tracing_subscriber::fmt()
         .with_max_level(tracing::Level::TRACE)
+        .with_writer(io::stderr)
         .finish();
 
     // In release mode, only log INFO and above.
     #[cfg(not(debug_assertions))]
     let subscriber = tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
+        .with_writer(io::stderr)
         .finish();
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    if args.read {
-        // Read the crawl data from the file and output the list of files to download
-        if !Path::new(&args.crawl_data_path).exists() {
-            error!("Crawl data file does not exist: {}", args.crawl_data_path);
-            // On Windows, the console window closes immediately after the program exits.
-            // To prevent this, we wait for user input before exiting.
-            #[cfg(windows)]
-            {
-                use std::io::prelude::*;
-                info!("Press Enter to exit...");
-                let _ = std::io::stdin().read(&mut [0u8]).unwrap();
-            }
-            process::exit(1);
+    let config_args = &args.config;
+    // Crawling and downloading add the names of their sites to the path themselves
+    let base_crawl_data_path = args.crawl_data_path.as_str();
+    let crawl_data_path = &site_path(base_crawl_data_path, config_args.site.as_deref());
+
+    match args.command.unwrap_or(Command::Download(args.download)) {
+        Command::Crawl(crawl_args) => {
+            crawl_command(config_args, base_crawl_data_path, &crawl_args, started).await
+        }
+        Command::Download(download_args) => {
+            download_command(config_args, base_crawl_data_path, &download_args, started).await
         }
+        Command::List { output } => list_command(crawl_data_path, output.as_deref()).await,
+        Command::Verify => verify_command(config_args, crawl_data_path).await,
+        Command::Diff { old, new, json } => diff_command(&old, &new, json).await,
+        Command::Export {
+            format,
+            output,
+            dir,
+        } => export_command(crawl_data_path, format, output.as_deref(), &dir).await,
+        Command::Init { force } => init_command(config_args, force).await,
+        Command::Import {
+            input,
+            base,
+            format,
+        } => {
+            import_command(
+                config_args,
+                crawl_data_path,
+                &input,
+                base.as_deref(),
+                format,
+            )
+            .await
+        }
+    }
+}
 
-        // Read the crawl data from the file
-        let data_str = tokio::fs::read(&args.crawl_data_path).await?;
+/// Crawls the websites and saves their crawl data.
+async fn crawl_command(
+    config_args: &ConfigArgs,
+    crawl_data_path: &str,
+    crawl_args: &CrawlArgs,
+    started: Instant,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let sites = load_sites(config_args, crawl_args.all_sites, None, started);
+    let progress = MultiProgress::new();
 
-        let crawl_data: CrawlData = bincode::deserialize(&data_str)?;
+    let status = run_sites(
+        &sites,
+        crawl_args.concurrent_sites,
+        None,
+        |site, _started| crawl_site(site, crawl_data_path, crawl_args, &progress),
+    )
+    .await;
 
-        // Create a text file with the list of files to download
-        let output_path = format!("{}_download_list.txt", args.crawl_data_path);
+    exit(status);
+}
+
+/// Crawls the website of a site and saves its crawl data.
+async fn crawl_site(
+    site: &Site,
+    crawl_data_path: &str,
+    crawl_args: &CrawlArgs,
+    progress: &MultiProgress,
+) -> Result<ExitStatus, Box<dyn std::error::Error + Send + Sync>> {
+    let config = &site.config;
+    let crawl_data_path = site.path(crawl_data_path);
+    let client = create_http_client(
+        &config.user_agent,
+        config.username.as_deref(),
+        config.password.as_deref(),
+    );
 
-        let mut output = tokio::fs::File::create(&output_path).await?;
+    let crawl_data = crawl(config, &client, &crawl_data_path, crawl_args, progress).await?;
 
-        output.write_all(crawl_data.to_string().as_bytes()).await?;
+    crawl_data.save(&crawl_data_path).await?;
+    info!(
+        "Saved crawl data of {} files ({}) to {}",
+        crawl_data.download_list.len(),
+        format_size(crawl_data.total_size),
+        crawl_data_path
+    );
 
-        info!("Download list written to {}", output_path);
+    Ok(ExitStatus::Success)
+}
 
-        // On Windows, the console window closes immediately after the program exits.
-        // To prevent this, we wait for user input before exiting.
-        #[cfg(windows)]
-        {
-            use std::io::prelude::*;
-            info!("Press Enter to exit...");
-            let _ = std::io::stdin().read(&mut [0u8]).unwrap();
-        }
+/// Downloads the files of the websites.
+async fn download_command(
+    config_args: &ConfigArgs,
+    crawl_data_path: &str,
+    args: &DownloadArgs,
+    started: Instant,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let report = args.report.as_deref();
 
-        process::exit(0);
+    if args.crawl.concurrent_sites && !args.yes {
+        error!("--concurrent-sites needs --yes, as the sites can't prompt at the same time.");
+        abort(
+            report,
+            ExitStatus::Error,
+            "--concurrent-sites needs --yes".to_string(),
+            started,
+        );
     }
 
+    let sites = load_sites(config_args, args.crawl.all_sites, report, started);
+    let progress = MultiProgress::new();
+
+    let status = run_sites(
+        &sites,
+        args.crawl.concurrent_sites,
+        report,
+        |site, started| download_site(site, crawl_data_path, args, &progress, started),
+    )
+    .await;
+
+    exit(status);
+}
+
+/// Downloads the files of a site, from a fresh crawl, the saved crawl data or the failures file.
+async fn download_site(
+    site: &Site,
+    crawl_data_path: &str,
+    args: &DownloadArgs,
+    progress: &MultiProgress,
+    started: Instant,
+) -> Result<ExitStatus, Box<dyn std::error::Error + Send + Sync>> {
+    let config = &site.config;
+    let crawl_data_path = &site.path(crawl_data_path);
+    let failures_path = &site.path(&args.failures_path);
+    let report = args.report.as_deref().map(|report| site.path(report));
+
     // Display confirmation of the arguments passed
-    if args.load_from_file {
-        info!("Loading crawl data from file: {}", args.crawl_data_path);
+    if args.retry_failed {
+        info!("Loading failed downloads from file: {}", failures_path);
+    } else if args.load {
+        info!("Loading crawl data from file: {}", crawl_data_path);
     } else {
         info!("Crawling website to generate crawl data...");
     }
 
-    if args.save_to_file {
-        info!("Saving crawl data to file: {}", args.crawl_data_path);
+    if args.save {
+        info!("Saving crawl data to file: {}", crawl_data_path);
     }
 
-    if args.load_from_file && args.save_to_file {
-        error!("Cannot load and save to the same file. Aborting.");
-        // On Windows, the console window closes immediately after the program exits.
-        // To prevent this, we wait for user input before exiting.
-        #[cfg(windows)]
-        {
-            use std::io::prelude::*;
-            info!("Press Enter to exit...");
-            let _ = std::io::stdin().read(&mut [0u8]).unwrap();
-        }
-        process::exit(1);
-    }
-
-    // Load the configuration from the config file.
-    let config = Config::from_file(DEFAULT_CONFIG_PATH).unwrap_or_else(|e| {
-        error!("Failed to load configuration: {}", e);
-
-        // Create a default configuration if loading fails
-        warn!(
-            "Creating default configuration file at {}",
-            DEFAULT_CONFIG_PATH
-        );
-
-        // Write the default configuration to the file
-        let config = Config::default();
-        let config_str = toml::to_string_pretty(&config).unwrap();
-        std::fs::write(DEFAULT_CONFIG_PATH, config_str).unwrap();
+    // Create an HTTP client with custom headers
+    let client = create_http_client(
+        &config.user_agent,
+        config.username.as_deref(),
+        config.password.as_deref(),
+    );
 
+    let crawl_data = if args.retry_failed {
+        let crawl_data = CrawlData::load(failures_path).await?;
         info!(
-            "Default configuration file created. Please edit the file and run the program again."
+            "Retrying {} failed downloads from {}",
+            crawl_data.download_list.len(),
+            failures_path
         );
+        crawl_data
+    } else if args.load {
+        let crawl_data = CrawlData::load(crawl_data_path).await?;
+        info!("Loaded crawl data from {}", crawl_data_path);
+        crawl_data
+    } else {
+        let crawl_data = crawl(config, &client, crawl_data_path, &args.crawl, progress).await?;
 
-        info!("The file is located at: {}", DEFAULT_CONFIG_PATH);
-
-        // On Windows, the console window closes immediately after the program exits.
-        // To prevent this, we wait for user input before exiting.
-        #[cfg(windows)]
-        {
-            use std::io::prelude::*;
-            info!("Press Enter to exit...");
-            let _ = std::io::stdin().read(&mut [0u8]).unwrap();
+        if args.save {
+            crawl_data.save(crawl_data_path).await?;
+            info!("Saved crawl data to {}", crawl_data_path);
         }
 
-        // Exit the program
-        process::exit(1);
-    });
+        crawl_data
+    };
 
-    trace!("Configuration loaded: {:#?}", config);
-
-    let crawl_data: CrawlData;
+    // Find the local files that were removed upstream
+    let sync_plan = if args.delete {
+        let source = ListingSource::from_config(config)?;
+        let root_url = Url::parse(&source.root_url(config)?)?;
+        let plan = plan_sync(
+            &crawl_data,
+            &config.output_dir,
+            args.trash_dir.as_deref(),
+            &root_url,
+            &config.filter,
+        )?;
 
-    // Create an HTTP client with custom headers
-    let client = create_http_client(&config.user_agent);
-
-    if args.load_from_file {
-        if !Path::new(&args.crawl_data_path).exists() {
-            error!("Crawl data file does not exist: {}", args.crawl_data_path);
-            // On Windows, the console window closes immediately after the program exits.
-            // To prevent this, we wait for user input before exiting.
-            #[cfg(windows)]
-            {
-                use std::io::prelude::*;
-                info!("Press Enter to exit...");
-                let _ = std::io::stdin().read(&mut [0u8]).unwrap();
+        if args.dry_run {
+            for file in &plan.files {
+                info!("Would delete {}", file);
             }
-            process::exit(1);
+            for directory in &plan.directories {
+                info!("Would remove directory {}", directory);
+            }
+            info!(
+                "Dry run: {} files and {} directories would be deleted.",
+                plan.files.len(),
+                plan.directories.len()
+            );
+            if plan.exceeds_max_delete(args.max_delete) {
+                warn!(
+                    "This is more than --max-delete {}, so the deletion would be aborted.",
+                    args.max_delete
+                );
+            }
+            return Ok(ExitStatus::Success);
         }
 
-        // Read the crawl data from the file
-        let data_str = tokio::fs::read(&args.crawl_data_path).await?;
-        crawl_data = bincode::deserialize(&data_str)?;
-        info!("Loaded crawl data from {}", args.crawl_data_path);
-    } else {
-        // Crawl the website and save the data if requested
-        info!("Crawling website: {}", config.url);
-        info!("Scanning website for files to download. This may take a very long time...");
-
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(
-            ProgressStyle::with_template("{spinner:.green} ({elapsed}) Hits: {pos:3} | {msg}")?
-                .progress_chars("─┼━"),
-        );
-        pb.set_message("Scanning...");
-        pb.enable_steady_tick(Duration::from_millis(150));
-
-        let (download_list, total_size, directories_to_create) = crawl_directory(
-            client.clone(),
-            config.url,
-            config.output_dir.clone(),
-            pb.clone(),
-            Arc::new(AtomicU64::from(0)),
-            Arc::from(config.filter.as_slice()),
-            "".to_string(),
-        )
-        .await?;
-
-        pb.finish_with_message("Scan complete.");
+        if plan.exceeds_max_delete(args.max_delete) {
+            return Err(format!(
+                "Refusing to delete {} files, which is more than --max-delete {}. Check the crawl data with --dry-run, or raise the limit.",
+                plan.files.len(),
+                args.max_delete
+            )
+            .into());
+        }
 
-        crawl_data = CrawlData {
-            download_list,
-            total_size,
-            directories_to_create,
-            saved_at: Utc::now(),
-        };
+        Some(plan)
+    } else {
+        None
+    };
 
-        if args.save_to_file {
-            let data_str = bincode::serialize(&crawl_data)?;
-            tokio::fs::write(&args.crawl_data_path, data_str).await?;
-            info!("Saved crawl data to {}", args.crawl_data_path);
-        }
+    // Display file names and prompt the user for confirmation
+    if !display_prompt(&crawl_data.download_list, crawl_data.total_size, args.yes).await? {
+        return Ok(ExitStatus::Success);
     }
 
-    if args.scan_only {
-        info!("Scan complete. Exiting without downloading any files.");
-        // On Windows, the console window closes immediately after the program exits.
-        // To prevent this, we wait for user input before exiting.
-        #[cfg(windows)]
-        {
-            use std::io::prelude::*;
-            info!("Press Enter to exit...");
-            let _ = std::io::stdin().read(&mut [0u8]).unwrap();
+    // Remove the local files that were removed upstream
+    if let Some(plan) = sync_plan {
+        if plan.is_empty() {
+            info!("No local files have to be deleted.");
+        } else {
+            let (files, directories) =
+                apply_sync(&plan, &config.output_dir, args.trash_dir.as_deref()).await?;
+            info!(
+                "Deleted {} files and {} directories no longer present upstream.",
+                files, directories
+            );
         }
-        process::exit(0);
     }
 
-    // Display file names and prompt the user for confirmation
-    display_prompt(&crawl_data.download_list, crawl_data.total_size, args.yes).await?;
-
     // Create directories for the files to download
     // Since we expect a large number of directories, we create them in parallel
     info!("Creating directories for the files...");
 
-    let create_dir_tasks = crawl_data.directories_to_create.iter().map(|dir| {
-        let dir = dir.clone();
-        let dir = percent_decode_str(&dir).decode_utf8_lossy().to_string();
+    // The output directory itself is created too, for the files at the root of the crawl
+    let output_dir = Path::new(&config.output_dir);
+    let directories = std::iter::once(output_dir.to_path_buf()).chain(
+        crawl_data
+            .directories_to_create
+            .iter()
+            .map(|dir| output_dir.join(percent_decode_str(dir).decode_utf8_lossy().as_ref())),
+    );
+    let create_dir_tasks = directories.map(|dir| {
         task::spawn(async move {
             create_dir_all(dir)?;
             Ok::<_, io::Error>(())
@@ -280,36 +571,538 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         }
 
         if failed {
-            tracing::error!("Failed to create directories for the files. Aborting download.");
-            // On Windows, the console window closes immediately after the program exits.
-            // To prevent this, we wait for user input before exiting.
-            #[cfg(windows)]
-            {
-                use std::io::prelude::*;
-                info!("Press Enter to exit...");
-                let _ = std::io::stdin().read(&mut [0u8]).unwrap();
-            }
-            process::exit(1);
+            return Err("Failed to create directories for the files. Aborting download.".into());
         }
     }
 
+    // Find the published checksums to verify the downloads against
+    let checksums = if config.checksums.enabled {
+        ChecksumIndex::fetch(
+            &client,
+            &crawl_data.download_list,
+            config.concurrent_downloads,
+        )
+        .await
+    } else {
+        ChecksumIndex::default()
+    };
+
     // After crawling, download files asynchronously in parallel
     info!("Downloading files...");
 
-    download_files_parallel(
+    let download_report = download_files_parallel(
         &client,
         crawl_data.download_list,
         &config.output_dir,
         config.concurrent_downloads,
         crawl_data.total_size,
+        DownloadOptions {
+            skip_policy: config.skip_policy,
+            retry: config.retry.clone(),
+            checksums,
+            on_mismatch: config.checksums.on_mismatch,
+            quarantine_dir: config.checksums.quarantine_dir.clone(),
+            progress: progress.clone(),
+        },
     )
     .await?;
 
+    let summary = RunSummary::from_report(&download_report, config.skip_policy, started.elapsed());
+    summary.log();
+
+    if let Some(report_path) = &report {
+        summary.write(report_path)?;
+        info!("Report written to {}", report_path);
+    }
+
+    if !download_report.failed.is_empty() {
+        let failed_count = download_report.failed.len();
+        CrawlData::from_failures(download_report.failed)
+            .save(failures_path)
+            .await?;
+        error!(
+            "{} files failed to download and were written to {}. Run `download --retry-failed` to retry only those files.",
+            failed_count, failures_path
+        );
+        return Ok(summary.status);
+    }
+
+    // Every file was downloaded, so an earlier failures file is out of date
+    if Path::new(failures_path).exists() {
+        tokio::fs::remove_file(failures_path).await?;
+        info!("Removed the failures file {}", failures_path);
+    }
+
     // Download complete!
     info!(
         "Download complete. Files have been saved to {}",
         config.output_dir
     );
 
-    Ok(())
+    Ok(ExitStatus::Success)
+}
+
+/// Writes the crawl data as a readable list of directories and files.
+async fn list_command(
+    crawl_data_path: &str,
+    output: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let crawl_data = load_crawl_data(crawl_data_path).await?;
+
+    match output {
+        Some(path) => {
+            let mut output = BufWriter::new(File::create(path)?);
+            write!(output, "{}", crawl_data)?;
+            output.flush()?;
+            info!("Download list written to {}", path);
+        }
+        None => {
+            let mut output = BufWriter::new(io::stdout().lock());
+            write!(output, "{}", crawl_data)?;
+            output.flush()?;
+        }
+    }
+
+    exit(ExitStatus::Success);
+}
+
+/// Checks the output directory against the crawl data and prints the report.
+async fn verify_command(
+    config_args: &ConfigArgs,
+    crawl_data_path: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let output_dir = match config_args.load(config_args.site.as_deref()) {
+        Ok(config) => config.output_dir,
+        Err(e) => {
+            error!(
+                "Failed to load configuration from {}:\n{}",
+                config_args.path(),
+                e
+            );
+            exit(ExitStatus::ConfigError);
+        }
+    };
+
+    let crawl_data = load_crawl_data(crawl_data_path).await?;
+    let report = verify_mirror(&crawl_data, &output_dir)?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    info!(
+        "Checked {} files in {}: {} ok, {} missing, {} wrong size, {} partial, {} extra",
+        report.checked,
+        output_dir,
+        report.ok + report.size_unknown,
+        report.missing.len(),
+        report.wrong_size.len(),
+        report.partial.len(),
+        report.extra.len()
+    );
+
+    if report.is_clean() {
+        exit(ExitStatus::Success);
+    }
+    exit(ExitStatus::MirrorMismatch);
+}
+
+/// Prints the differences between two crawl data files.
+async fn diff_command(
+    old: &str,
+    new: &str,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let diff = diff_crawl_data(&load_crawl_data(old).await?, &load_crawl_data(new).await?);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        println!("{}", diff);
+    }
+
+    exit(ExitStatus::Success);
+}
+
+/// Exports the file list of the crawl data.
+async fn export_command(
+    crawl_data_path: &str,
+    format: ExportFormat,
+    output: Option<&str>,
+    dir: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let crawl_data = load_crawl_data(crawl_data_path).await?;
+
+    match output {
+        Some(path) => {
+            export(
+                &crawl_data,
+                format,
+                dir,
+                BufWriter::new(File::create(path)?),
+            )?;
+            info!(
+                "Exported {} files to {}",
+                crawl_data.download_list.len(),
+                path
+            );
+        }
+        None => export(
+            &crawl_data,
+            format,
+            dir,
+            BufWriter::new(io::stdout().lock()),
+        )?,
+    }
+
+    exit(ExitStatus::Success);
+}
+
+/// Writes a configuration file with the default settings and the overrides.
+async fn init_command(
+    config_args: &ConfigArgs,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut config = Config::default();
+    config_args.apply(&mut config);
+
+    let errors = config.validate();
+    if !errors.is_empty() {
+        error!("Invalid settings:\n{}", errors.join("\n"));
+        exit(ExitStatus::ConfigError);
+    }
+
+    match config.write(config_args.path(), force) {
+        Ok(()) => info!("Configuration written to {}", config_args.path()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            error!(
+                "{} already exists. Run `init --force` to replace it with the default configuration.",
+                config_args.path()
+            );
+            exit(ExitStatus::ConfigError);
+        }
+        Err(e) => {
+            error!("Failed to write {}: {}", config_args.path(), e);
+            exit(ExitStatus::ConfigError);
+        }
+    }
+
+    exit(ExitStatus::Success);
+}
+
+/// Builds crawl data from a list of URLs and saves it.
+async fn import_command(
+    config_args: &ConfigArgs,
+    crawl_data_path: &str,
+    input: &str,
+    base: Option<&str>,
+    format: Option<ImportFormat>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = match config_args.load(config_args.site.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            error!(
+                "Failed to load configuration from {}:\n{}",
+                config_args.path(),
+                e
+            );
+            exit(ExitStatus::ConfigError);
+        }
+    };
+
+    let base = match Url::parse(base.unwrap_or(&config.url)) {
+        Ok(base) => base,
+        Err(e) => {
+            error!("Invalid base URL: {}", e);
+            exit(ExitStatus::ConfigError);
+        }
+    };
+
+    let format = format.unwrap_or_else(|| ImportFormat::from_path(input));
+    let crawl_data = import_urls(input, format, &base)?;
+
+    if crawl_data.download_list.is_empty() {
+        error!("No files to download were found in {}", input);
+        exit(ExitStatus::Error);
+    }
+
+    crawl_data.save(crawl_data_path).await?;
+    info!(
+        "Imported {} files ({}) to {}. Run `download --load` to download them.",
+        crawl_data.download_list.len(),
+        format_size(crawl_data.total_size),
+        crawl_data_path
+    );
+
+    exit(ExitStatus::Success);
+}
+
+/// Loads the configuration of the sites to run: the one given with `--site`, every site with
+/// `--all-sites`, or else the global settings.
+fn load_sites(
+    config_args: &ConfigArgs,
+    all_sites: bool,
+    report: Option<&str>,
+    started: Instant,
+) -> Vec<Site> {
+    let names = if all_sites {
+        let config = load_config(config_args, None, report, started);
+        if config.site.is_empty() {
+            let e = format!(
+                "There are no [site.<name>] tables in {}",
+                config_args.path()
+            );
+            error!("{}", e);
+            abort(report, ExitStatus::ConfigError, e, started);
+        }
+        config.site.into_keys().map(Some).collect()
+    } else {
+        vec![config_args.site.clone()]
+    };
+
+    names
+        .into_iter()
+        .map(|name| Site {
+            config: load_config(config_args, name.as_deref(), report, started),
+            name,
+        })
+        .collect()
+}
+
+/// Runs the command for each site, one after another or at the same time, and returns the
+/// highest exit status of the sites. The logs of each site are tagged with its name, and the
+/// report of a site that fails is written to its own copy of the report path.
+async fn run_sites<'a, F, Fut>(
+    sites: &'a [Site],
+    concurrent: bool,
+    report: Option<&str>,
+    run: F,
+) -> ExitStatus
+where
+    F: Fn(&'a Site, Instant) -> Fut,
+    Fut: Future<Output = Result<ExitStatus, Box<dyn std::error::Error + Send + Sync>>>,
+{
+    let run_site = |site: &'a Site| {
+        let started = Instant::now();
+        let span = match &site.name {
+            Some(name) => info_span!("site", name = %name),
+            None => Span::none(),
+        };
+        let future = run(site, started);
+
+        async move {
+            match future.await {
+                Ok(status) => status,
+                Err(e) => {
+                    error!("{}", e);
+                    let status = RunError::status_of(e.as_ref());
+                    if let Some(report_path) = report.map(|report| site.path(report)) {
+                        let summary = RunSummary::aborted(status, e.to_string(), started.elapsed());
+                        if let Err(e) = summary.write(&report_path) {
+                            error!("Failed to write the report to {}: {}", report_path, e);
+                        }
+                    }
+                    status
+                }
+            }
+        }
+        .instrument(span)
+    };
+
+    let statuses = if concurrent {
+        futures::future::join_all(sites.iter().map(run_site)).await
+    } else {
+        let mut statuses = Vec::new();
+        for site in sites {
+            statuses.push(run_site(site).await);
+        }
+        statuses
+    };
+
+    if sites.len() > 1 {
+        for (site, status) in sites.iter().zip(&statuses) {
+            let name = site.name.as_deref().unwrap_or_default();
+            if *status == ExitStatus::Success {
+                info!("Site {}: {:?}", name, status);
+            } else {
+                error!("Site {}: {:?}", name, status);
+            }
+        }
+    }
+
+    statuses
+        .into_iter()
+        .max_by_key(|status| status.code())
+        .unwrap_or(ExitStatus::Success)
+}
+
+/// Loads the configuration file, of the site if one is given, and applies the overrides. If the
+/// file doesn't exist, a default configuration is written in its place and the program exits.
+/// A file that can't be loaded is left as it is.
+fn load_config(
+    config_args: &ConfigArgs,
+    site: Option<&str>,
+    report: Option<&str>,
+    started: Instant,
+) -> Config {
+    // Without a file, the settings may all come from the environment and command line
+    if site.is_none() && !config_args.has_overrides() && !Path::new(config_args.path()).exists() {
+        let e = format!("Configuration file not found: {}", config_args.path());
+        error!("{}", e);
+
+        // Create a default configuration in its place
+        warn!(
+            "Creating default configuration file at {}",
+            config_args.path()
+        );
+
+        // Write the default configuration to the file
+        if let Err(write_error) = Config::default().write(config_args.path(), false) {
+            error!(
+                "Failed to create the configuration file at {}: {}",
+                config_args.path(),
+                write_error
+            );
+            abort(report, ExitStatus::ConfigError, e, started);
+        }
+
+        info!(
+            "Default configuration file created. Please edit the file and run the program again."
+        );
+
+        info!("The file is located at: {}", config_args.path());
+
+        abort(report, ExitStatus::ConfigError, e, started);
+    }
+
+    let config = config_args.load(site).unwrap_or_else(|e| {
+        error!(
+            "Failed to load configuration from {}:\n{}",
+            config_args.path(), e
+        );
+        if Path::new(config_args.path()).exists() {
+            info!("The file was left unchanged. Fix the errors above, or run `init --force` to replace it with the default configuration.");
+        }
+        abort(report, ExitStatus::ConfigError, e.to_string(), started);
+    });
+
+    trace!("Configuration loaded: {:#?}", config);
+
+    config
+}
+
+/// Loads the crawl data file, exiting if it doesn't exist.
+async fn load_crawl_data(
+    path: &str,
+) -> Result<CrawlData, Box<dyn std::error::Error + Send + Sync>> {
+    if !Path::new(path).exists() {
+        error!("Crawl data file does not exist: {}", path);
+        exit(ExitStatus::Error);
+    }
+
+    CrawlData::load(path).await
+}
+
+/// Crawls the website, reusing the unchanged directory listings of the saved crawl data.
+async fn crawl(
+    config: &Config,
+    client: &Client,
+    crawl_data_path: &str,
+    crawl_args: &CrawlArgs,
+    progress: &MultiProgress,
+) -> Result<CrawlData, Box<dyn std::error::Error + Send + Sync>> {
+    info!("Crawling website: {}", config.url);
+    info!("Scanning website for files to download. This may take a very long time...");
+
+    let pb = progress.add(ProgressBar::new_spinner());
+    pb.set_style(
+        ProgressStyle::with_template("{spinner:.green} ({elapsed}) Hits: {pos:3} | {msg}")?
+            .progress_chars("─┼━"),
+    );
+    pb.set_message("Scanning...");
+    pb.enable_steady_tick(Duration::from_millis(150));
+
+    let invalid = |e| {
+        RunError::new(
+            ExitStatus::ConfigError,
+            format!("Invalid configuration: {}", e),
+        )
+    };
+    let source = ListingSource::from_config(config).map_err(invalid)?;
+    let root_url = source.root_url(config).map_err(invalid)?;
+
+    let checkpoint_path = format!("{}.checkpoint", crawl_data_path);
+
+    // The listings of the last saved crawl let unchanged directories be skipped
+    let mut cache = HashMap::new();
+    if !crawl_args.full && Path::new(crawl_data_path).exists() {
+        match CrawlData::load(crawl_data_path).await {
+            Ok(previous) => {
+                debug!(
+                    "Loaded {} cached directory listings from {}",
+                    previous.listings.len(),
+                    crawl_data_path
+                );
+                cache = previous.listings;
+            }
+            Err(e) => warn!(
+                "Failed to load the listing cache from {}, listing every directory: {}",
+                crawl_data_path, e
+            ),
+        }
+    }
+
+    let crawler = Crawler {
+        client: client.clone(),
+        source: Arc::new(source),
+        filters: Arc::from(config.filter.as_slice()),
+        concurrency: config.crawl_concurrency,
+        order: config.crawl_order,
+        pb: pb.clone(),
+        checkpoint_path: Some(checkpoint_path.clone()),
+        checkpoint_interval: Duration::from_secs(config.checkpoint_interval_secs),
+        cache: Arc::new(cache),
+    };
+
+    let result = if crawl_args.resume {
+        if !Path::new(&checkpoint_path).exists() {
+            return Err(
+                format!("Crawl checkpoint file does not exist: {}", checkpoint_path).into(),
+            );
+        }
+
+        let checkpoint = CrawlCheckpoint::load(&checkpoint_path).await?;
+
+        if checkpoint.root_url != root_url {
+            return Err(format!(
+                "Crawl checkpoint is for {}, not {}. Aborting.",
+                checkpoint.root_url, root_url
+            )
+            .into());
+        }
+
+        info!(
+            "Resuming crawl from {} ({} directories scanned, {} pending, saved at {})",
+            checkpoint_path,
+            checkpoint.visited.len(),
+            checkpoint.pending.len(),
+            checkpoint.saved_at
+        );
+        pb.set_position(
+            (checkpoint.download_list.len() + checkpoint.directories_to_create.len()) as u64,
+        );
+
+        crawler.resume(checkpoint).await
+    } else {
+        crawler.crawl(root_url).await
+    };
+
+    match result {
+        Ok(crawl_data) => {
+            pb.finish_with_message("Scan complete.");
+            Ok(crawl_data)
+        }
+        Err(e) => {
+            pb.abandon_with_message("Scan failed.");
+            Err(RunError::new(ExitStatus::CrawlFailure, e.to_string()).into())
+        }
+    }
 }
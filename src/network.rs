@@ -1,50 +1,221 @@
 use std::{
-    future::Future,
-    pin::Pin,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::Display,
+    path::Path,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
+use chrono::{DateTime, Utc};
 use futures::StreamExt;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use percent_encoding::percent_decode_str;
-use reqwest::{Client, Response, Url};
-use scraper::Selector;
-use tokio::{fs::File, io::AsyncWriteExt, sync::Semaphore};
+use reqwest::{
+    header::{
+        CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE,
+        LAST_MODIFIED, RANGE, RETRY_AFTER, SERVER,
+    },
+    Client, RequestBuilder, Response, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Semaphore,
+    task::JoinSet,
+};
 use tokio_retry2::{
-    strategy::{jitter, ExponentialBackoff, MaxInterval},
-    Retry, RetryError,
+    strategy::{jitter, ExponentialBackoff, ExponentialFactorBackoff, MaxInterval},
+    Retry, RetryError, RetryIf,
 };
-use tracing::{debug, trace, warn};
+use tracing::{debug, info, trace, warn, Instrument};
 
 use crate::{
-    config::FilterRule,
-    crawl_data::DownloadData,
-    utils::{format_size, get_file_size, should_filter, should_skip_url, truncate_string},
+    checkpoint::CrawlCheckpoint,
+    checksum::{Checksum, ChecksumIndex, Hasher},
+    config::{
+        Backend, Config, CrawlOrder, FilterRule, MismatchAction, RetryConfig, ServerFlavour,
+        SkipPolicy,
+    },
+    crawl_data::{
+        CachedListing, CrawlData, DownloadData, FailedDirectory, FailedDownload, FailureKind,
+    },
+    index_parser::{parser_for, IndexParser, ListingEntry, NginxJsonParser, NginxXmlParser},
+    s3,
+    utils::{
+        encode_path, format_size, get_file_metadata, move_file, parse_last_modified, should_filter,
+        truncate_string, RemoteMetadata,
+    },
+    webdav,
 };
 
-/// What the fuck, i mean it works at least ig
-type CrawlDirectoryResult = Pin<
-    Box<
-        dyn Future<
-                Output = Result<
-                    (Vec<DownloadData>, u64, Vec<String>),
-                    Box<dyn std::error::Error + Send + Sync>,
-                >,
-            > + Send,
-    >,
->;
-
-/// GET url
-async fn get_url(
+/// Where directory listings are read from.
+#[derive(Debug)]
+pub enum ListingSource {
+    /// Index pages generated by a web server.
+    Index(ServerFlavour),
+    /// An S3-compatible bucket, listed with `ListObjectsV2`.
+    S3 { bucket_url: Url },
+    /// A WebDAV server, listed with `PROPFIND` requests.
+    WebDav,
+}
+
+impl ListingSource {
+    /// Creates the listing source for the configured backend.
+    pub fn from_config(config: &Config) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(match config.backend {
+            Backend::Index => ListingSource::Index(config.server),
+            Backend::S3 => {
+                let mut bucket_url = Url::parse(&config.url)?;
+                // Path-style bucket URLs need a trailing slash so keys are joined below the bucket
+                if !bucket_url.path().ends_with('/') {
+                    bucket_url.set_path(&format!("{}/", bucket_url.path()));
+                }
+                ListingSource::S3 { bucket_url }
+            }
+            Backend::WebDav => ListingSource::WebDav,
+        })
+    }
+
+    /// Returns the URL of the directory the crawl starts from.
+    pub fn root_url(
+        &self,
+        config: &Config,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(match self {
+            ListingSource::Index(_) | ListingSource::WebDav => config.url.clone(),
+            ListingSource::S3 { bucket_url } => {
+                let prefix = config.s3_prefix.trim_matches('/');
+                if prefix.is_empty() {
+                    bucket_url.to_string()
+                } else {
+                    bucket_url
+                        .join(&format!("{}/", encode_path(prefix)))?
+                        .to_string()
+                }
+            }
+        })
+    }
+}
+
+/// The response to a listing request.
+enum Listing {
+    /// The entries of the directory, with the validators of the listing page if there are any.
+    Entries {
+        entries: Vec<ListingEntry>,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+    /// The listing page hasn't changed since it was cached.
+    NotModified,
+}
+
+/// Lists the entries of the directory at the given URL.
+///
+/// Index pages are requested conditionally if a cached listing with validators is given.
+async fn list_directory(
+    client: &Client,
+    url: &str,
+    source: &ListingSource,
+    cached: Option<&CachedListing>,
+) -> Result<Listing, Box<dyn std::error::Error + Send + Sync>> {
+    let entries = |entries| Listing::Entries {
+        entries,
+        etag: None,
+        last_modified: None,
+    };
+
+    match source {
+        ListingSource::Index(flavour) => {
+            let mut request = client.get(url);
+            if let Some(cached) = cached {
+                if let Some(etag) = &cached.etag {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &cached.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+
+            let response = send_with_retry(request).await?;
+            let status = response.status();
+            if status == StatusCode::NOT_MODIFIED {
+                return Ok(Listing::NotModified);
+            }
+            // An error page would be parsed as an empty directory, losing everything below it
+            if !status.is_success() {
+                return Err(format!("Listing {} failed: {}", url, status).into());
+            }
+
+            let header = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned)
+            };
+            let server = header(SERVER);
+            let content_type = header(CONTENT_TYPE);
+            let etag = header(ETAG);
+            let last_modified = header(LAST_MODIFIED);
+
+            Ok(Listing::Entries {
+                entries: extract_entries(
+                    &response.text().await?,
+                    url,
+                    server.as_deref(),
+                    content_type.as_deref(),
+                    *flavour,
+                )?,
+                etag,
+                last_modified,
+            })
+        }
+        ListingSource::S3 { bucket_url } => Ok(entries(
+            s3::list_prefix(client, bucket_url, &Url::parse(url)?).await?,
+        )),
+        ListingSource::WebDav => Ok(entries(
+            webdav::list_collection(client, &Url::parse(url)?).await?,
+        )),
+    }
+}
+
+/// Sends a GET request to the URL, retrying on connection errors.
+pub async fn fetch_url(
     client: &Client,
     url: &str,
+) -> Result<Response, Box<dyn std::error::Error + Send + Sync>> {
+    send_with_retry(client.get(url)).await
+}
+
+/// Sends the request, retrying on connection errors.
+pub async fn send_with_retry(
+    request: RequestBuilder,
+) -> Result<Response, Box<dyn std::error::Error + Send + Sync>> {
+    let retry_strategy = ExponentialBackoff::from_millis(10)
+        .factor(1)
+        .max_delay_millis(100)
+        .max_interval(10000)
+        .map(jitter)
+        .take(150);
+
+    Retry::spawn_notify(retry_strategy, || send_request(&request), notify).await
+}
+
+/// Send a copy of the request
+async fn send_request(
+    request: &RequestBuilder,
 ) -> Result<Response, RetryError<Box<dyn std::error::Error + Send + Sync>>> {
-    let result = client.get(url).send().await;
+    let request = request
+        .try_clone()
+        .ok_or_else(|| RetryError::permanent("Request body cannot be retried".into()))?;
 
-    result
+    request
+        .send()
+        .await
         .map_err(|e| RetryError::transient(Box::new(e) as Box<dyn std::error::Error + Send + Sync>))
 }
 
@@ -54,147 +225,432 @@ fn notify(err: &Box<dyn std::error::Error + Send + Sync>, duration: std::time::D
     warn!("Error {err} occurred at {duration:?}");
 }
 
-/// Crawls the directory at the given URL and collects files to download.
-pub fn crawl_directory(
-    client: Client,
-    url: String,
-    output_dir: String,
-    pb: ProgressBar,
-    total_size: Arc<AtomicU64>,
-    filters: Arc<[FilterRule]>,
-    root_relative_path: String,
-) -> CrawlDirectoryResult {
-    Box::pin(async move {
-        trace!("Crawling link: {}", url);
-        // Initialize the tables
-        let mut files_to_download: Vec<DownloadData> = Vec::new();
-        let mut directories_to_create: Vec<String> = Vec::new();
-
-        let retry_strategy = ExponentialBackoff::from_millis(10)
-            .factor(1)
-            .max_delay_millis(100)
-            .max_interval(10000)
-            .map(jitter)
-            .take(150);
-
-        // Send a GET request to the URL
-        // let response = client.get(&url).send().await?;
-        let response: Response =
-            Retry::spawn_notify(retry_strategy, || get_url(&client, &url), notify).await?;
-
-        // Filter the response content to get the directories and files urls
-        let links = extract_links(&response.text().await?, &url, &filters).await?;
-
-        // Concurrently crawl each link
-        let mut tasks = Vec::new();
-
-        for (href, link, is_dir) in links {
-            let formatted_size = format_size(total_size.load(Ordering::SeqCst));
-            pb.set_message(format!("({:6}) Scanning: {}", formatted_size, link));
-            pb.inc(1);
-            if is_dir {
-                // Create a task to crawl the directory
-                let client = client.clone();
-
-                let total_size = total_size.clone();
-
-                let pb = pb.clone();
-
-                let filters = filters.clone();
-
-                let new_root_relative_path = if root_relative_path.is_empty() {
-                    href.to_string()
-                } else {
-                    format!("{}/{}", root_relative_path, href)
+/// A directory waiting to be listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingDirectory {
+    pub url: String,
+    /// Path of the directory relative to the crawl root (percent-encoded), empty for the root.
+    pub relative_path: String,
+}
+
+impl PendingDirectory {
+    /// Returns the path of a child entry relative to the crawl root.
+    fn child_path(&self, name: &str) -> String {
+        if self.relative_path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", self.relative_path, name)
+        }
+    }
+}
+
+/// The files and subdirectories found in one directory.
+struct DirectoryListing {
+    directory: PendingDirectory,
+    files: Vec<DownloadData>,
+    subdirectories: Vec<PendingDirectory>,
+    /// The listing to cache for the next crawl, if the listing page has validators.
+    cache: Option<CachedListing>,
+    /// Whether the entries were reused from the cache of an earlier crawl.
+    reused: bool,
+}
+
+/// Crawls a directory tree with a bounded number of concurrent listing requests.
+pub struct Crawler {
+    pub client: Client,
+    pub source: Arc<ListingSource>,
+    pub filters: Arc<[FilterRule]>,
+    /// Maximum number of directories listed at the same time.
+    pub concurrency: usize,
+    pub order: CrawlOrder,
+    pub pb: ProgressBar,
+    /// File the crawl progress is saved to, so an interrupted crawl can be resumed.
+    pub checkpoint_path: Option<String>,
+    /// How often the progress is saved to the checkpoint file.
+    pub checkpoint_interval: Duration,
+    /// Directory listings of an earlier crawl, reused for the directories that are unchanged.
+    pub cache: Arc<HashMap<String, CachedListing>>,
+}
+
+impl Crawler {
+    /// Crawls the directory tree at the given URL and collects files to download.
+    pub async fn crawl(
+        &self,
+        root_url: String,
+    ) -> Result<CrawlData, Box<dyn std::error::Error + Send + Sync>> {
+        self.resume(CrawlCheckpoint::new(root_url)).await
+    }
+
+    /// Continues the crawl saved in the checkpoint and collects files to download.
+    ///
+    /// Directories that can't be listed are recorded in the crawl data, and the crawl continues
+    /// without them. Only a failure to list the root directory fails the crawl.
+    ///
+    /// If a checkpoint path is set, the progress is saved there periodically, when the crawl
+    /// fails and when Ctrl-C is pressed. It is removed once the crawl completes.
+    pub async fn resume(
+        &self,
+        mut checkpoint: CrawlCheckpoint,
+    ) -> Result<CrawlData, Box<dyn std::error::Error + Send + Sync>> {
+        let mut queue: VecDeque<PendingDirectory> = std::mem::take(&mut checkpoint.pending).into();
+        let mut in_flight = JoinSet::new();
+        let mut in_flight_directories: HashMap<String, PendingDirectory> = HashMap::new();
+        let mut last_checkpoint = Instant::now();
+        let mut reused = 0;
+
+        let ctrl_c = tokio::signal::ctrl_c();
+        tokio::pin!(ctrl_c);
+
+        loop {
+            // Keep up to `concurrency` listing requests in flight
+            while in_flight.len() < self.concurrency.max(1) {
+                let next = match self.order {
+                    CrawlOrder::BreadthFirst => queue.pop_front(),
+                    CrawlOrder::DepthFirst => queue.pop_back(),
+                };
+                let Some(directory) = next else {
+                    break;
                 };
-                let new_output_dir = format!("{}/{}", output_dir, href);
-
-                // Add to the list of directories to create
-                directories_to_create.push(format!("{}/{}", new_output_dir, href));
-
-                let new_url = link.to_string();
-
-                let task = tokio::task::spawn(async move {
-                    Box::pin(crawl_directory(
-                        client,
-                        new_url.clone(),
-                        new_output_dir,
-                        pb,
-                        total_size,
-                        filters,
-                        new_root_relative_path,
-                    ))
-                    .await
-                });
 
-                tasks.push(task);
-            } else {
-                // Add the file to the download list
-                let file_size = get_file_size(&client, &link).await.unwrap_or_else(|_| {
-                    warn!("Failed to get file size for {}", link);
-                    0
-                });
+                if checkpoint.visited.contains(&directory.url) {
+                    continue;
+                }
 
-                total_size.fetch_add(file_size, Ordering::SeqCst);
+                in_flight_directories.insert(directory.url.clone(), directory.clone());
+                let cached = self.cache.get(&directory.url).cloned();
+                let listing = list_pending_directory(
+                    self.client.clone(),
+                    self.source.clone(),
+                    self.filters.clone(),
+                    directory.clone(),
+                    cached,
+                );
+                in_flight.spawn(
+                    async move { listing.await.map_err(|e| (directory, e)) }.in_current_span(),
+                );
+            }
 
-                files_to_download.push(DownloadData {
-                    url: link.to_string(),
-                    output_dir: format!("{}/{}", root_relative_path, href),
-                });
+            let result = tokio::select! {
+                result = in_flight.join_next() => match result {
+                    Some(result) => result,
+                    // Once nothing is in flight the queue is empty too, and the crawl is done
+                    None => break,
+                },
+                _ = &mut ctrl_c => {
+                    self.save_checkpoint(&mut checkpoint, &queue, &in_flight_directories)
+                        .await?;
+                    return Err(self.interrupted_message("Crawl interrupted").into());
+                }
+            };
+
+            let listing = match result {
+                Ok(Ok(listing)) => listing,
+                // Without the root listing there is nothing to crawl
+                Ok(Err((directory, e))) if directory.relative_path.is_empty() => {
+                    self.save_checkpoint(&mut checkpoint, &queue, &in_flight_directories)
+                        .await?;
+                    return Err(self
+                        .interrupted_message(&format!("Crawl failed: {}", e))
+                        .into());
+                }
+                // Any other directory is recorded as failed, and the crawl goes on without it
+                Ok(Err((directory, e))) => {
+                    warn!("Failed to list {}: {}", directory.url, e);
+                    in_flight_directories.remove(&directory.url);
+                    checkpoint.visited.insert(directory.url.clone());
+                    checkpoint.failed_directories.push(FailedDirectory {
+                        url: directory.url,
+                        path: directory.relative_path,
+                        message: e.to_string(),
+                    });
+                    continue;
+                }
+                Err(e) => {
+                    self.save_checkpoint(&mut checkpoint, &queue, &in_flight_directories)
+                        .await?;
+                    return Err(e.into());
+                }
+            };
+
+            in_flight_directories.remove(&listing.directory.url);
+            checkpoint.visited.insert(listing.directory.url.clone());
+            if let Some(cache) = listing.cache {
+                checkpoint
+                    .listings
+                    .insert(listing.directory.url.clone(), cache);
+            }
+            if listing.reused {
+                reused += 1;
+            }
+
+            for file in &listing.files {
+                checkpoint.total_size += file.size.unwrap_or(0);
+                self.pb.inc(1);
+            }
+
+            for directory in &listing.subdirectories {
+                checkpoint
+                    .directories_to_create
+                    .push(directory.relative_path.clone());
+                self.pb.inc(1);
+            }
+
+            self.pb.set_message(format!(
+                "({:6}) Queued: {:6} | Scanned: {}",
+                format_size(checkpoint.total_size),
+                queue.len() + listing.subdirectories.len(),
+                listing.directory.url
+            ));
+
+            checkpoint.download_list.extend(listing.files);
+            queue.extend(listing.subdirectories);
+
+            if last_checkpoint.elapsed() >= self.checkpoint_interval {
+                self.save_checkpoint(&mut checkpoint, &queue, &in_flight_directories)
+                    .await?;
+                last_checkpoint = Instant::now();
             }
         }
 
-        // Wait for all tasks to complete
-        let results = futures::future::join_all(tasks).await;
+        if let Some(path) = &self.checkpoint_path {
+            if Path::new(path).exists() {
+                tokio::fs::remove_file(path).await?;
+            }
+        }
 
-        // Collect the results from the tasks
-        for result in results {
-            let (files, _, dirs) = result??;
-            files_to_download.extend(files);
-            directories_to_create.extend(dirs);
+        if !self.cache.is_empty() {
+            info!(
+                "Reused {} of {} directory listings that were unchanged since the last crawl",
+                reused,
+                checkpoint.visited.len()
+            );
         }
 
-        Ok((
-            files_to_download,
-            total_size.load(Ordering::SeqCst),
-            directories_to_create,
-        ))
-    })
+        if !checkpoint.failed_directories.is_empty() {
+            warn!(
+                "{} directories couldn't be listed, so the files below them are missing from the crawl:",
+                checkpoint.failed_directories.len()
+            );
+            for directory in &checkpoint.failed_directories {
+                warn!("{}", directory);
+            }
+        }
+
+        Ok(CrawlData {
+            download_list: checkpoint.download_list,
+            total_size: checkpoint.total_size,
+            directories_to_create: checkpoint.directories_to_create,
+            saved_at: Utc::now(),
+            failures: Vec::new(),
+            listings: checkpoint.listings,
+            failed_directories: checkpoint.failed_directories,
+        })
+    }
+
+    /// Saves the crawl progress to the checkpoint file, if one is set.
+    async fn save_checkpoint(
+        &self,
+        checkpoint: &mut CrawlCheckpoint,
+        queue: &VecDeque<PendingDirectory>,
+        in_flight_directories: &HashMap<String, PendingDirectory>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(path) = &self.checkpoint_path else {
+            return Ok(());
+        };
+
+        // Directories still being listed are saved as pending, so they are listed again
+        checkpoint.pending = queue
+            .iter()
+            .chain(in_flight_directories.values())
+            .cloned()
+            .collect();
+        checkpoint.saved_at = Utc::now();
+        checkpoint.save(path).await?;
+
+        debug!(
+            "Saved crawl checkpoint to {} ({} visited, {} pending)",
+            path,
+            checkpoint.visited.len(),
+            checkpoint.pending.len()
+        );
+
+        Ok(())
+    }
+
+    /// Builds the error message for a crawl that stopped early.
+    fn interrupted_message(&self, reason: &str) -> String {
+        match &self.checkpoint_path {
+            Some(path) => format!(
+                "{}. Progress was saved to {}, run again with --resume to continue.",
+                reason, path
+            ),
+            None => reason.to_string(),
+        }
+    }
 }
 
-/// Extracts links from the HTML content using the given root relative path.
-async fn extract_links(
-    content: &str,
-    url: &str,
-    filters: &[FilterRule],
-) -> Result<Vec<(String, Url, bool)>, Box<dyn std::error::Error + Send + Sync>> {
-    trace!("Extracting links from content");
+/// Lists a single directory, splitting its entries into files and subdirectories.
+///
+/// If the server reports that the listing is unchanged since it was cached, the cached entries
+/// are reused. Its subdirectories are still listed, as a directory listing doesn't change when
+/// something deeper in the tree does.
+///
+/// All entries are cached, including those the filters exclude, and the filters are applied to
+/// fresh and cached entries alike, so a later crawl with other filters still finds every entry.
+async fn list_pending_directory(
+    client: Client,
+    source: Arc<ListingSource>,
+    filters: Arc<[FilterRule]>,
+    directory: PendingDirectory,
+    cached: Option<CachedListing>,
+) -> Result<DirectoryListing, Box<dyn std::error::Error + Send + Sync>> {
+    trace!("Crawling link: {}", directory.url);
 
-    let document = scraper::Html::parse_document(content);
-    let selector = Selector::parse("a").unwrap();
+    // List the directory to get the directories and files
+    let (entries, etag, last_modified, reused) = match (
+        list_directory(&client, &directory.url, &source, cached.as_ref()).await?,
+        cached,
+    ) {
+        (
+            Listing::Entries {
+                entries,
+                etag,
+                last_modified,
+            },
+            _,
+        ) => (entries, etag, last_modified, false),
+        (Listing::NotModified, Some(cached)) => {
+            trace!("Listing unchanged: {}", directory.url);
+            (cached.entries, cached.etag, cached.last_modified, true)
+        }
+        (Listing::NotModified, None) => {
+            return Err(format!("Unexpected 304 Not Modified for {}", directory.url).into())
+        }
+    };
 
-    let mut links: Vec<(String, Url, bool)> = Vec::new();
+    let mut listing = DirectoryListing {
+        directory: directory.clone(),
+        files: Vec::new(),
+        subdirectories: Vec::new(),
+        cache: None,
+        reused,
+    };
+    let mut cached_entries = Vec::with_capacity(entries.len());
 
-    for element in document.select(&selector) {
-        if let Some(href) = element.value().attr("href") {
-            trace!("Found link: {}", href);
+    for mut entry in entries {
+        trace!("Found entry: {}", entry.url);
 
-            if should_skip_url(href) {
-                continue;
-            }
+        if should_filter(entry.url.path(), &filters).unwrap_or(false) {
+            cached_entries.push(entry);
+            continue;
+        }
 
-            let full_url = Url::parse(url)?.join(href)?;
-            let relative_path = full_url.path();
+        // The paths are built from the entry names, as the crawl may start from a different
+        // root than the crawl that cached them
+        if entry.is_dir {
+            listing.subdirectories.push(PendingDirectory {
+                url: entry.url.to_string(),
+                relative_path: directory.child_path(&entry.name),
+            });
+            cached_entries.push(entry);
+            continue;
+        }
 
-            if should_filter(relative_path, filters).unwrap_or(false) {
-                continue;
+        // Use the size and modification time from the listing, and only fall back to a
+        // HEAD request if the listing doesn't show a size. The result is kept in the cached
+        // entry, so it isn't requested again while the listing is unchanged.
+        if entry.size.is_none() {
+            let metadata = get_file_metadata(&client, &entry.url)
+                .await
+                .unwrap_or_else(|_| {
+                    warn!("Failed to get file size for {}", entry.url);
+                    RemoteMetadata::default()
+                });
+            entry.size = metadata.size;
+            entry.size_exact = metadata.size.is_some();
+            if metadata.modified.is_some() {
+                entry.mtime = metadata.modified;
+                entry.mtime_exact = true;
             }
-
-            links.push((href.to_string(), full_url, href.ends_with('/')));
         }
+
+        // Add the file to the download list
+        listing.files.push(DownloadData {
+            url: entry.url.to_string(),
+            output_dir: directory.child_path(&entry.name),
+            size: entry.size,
+            size_exact: entry.size_exact,
+            modified: entry.mtime,
+            modified_exact: entry.mtime_exact,
+            etag: entry.etag.clone(),
+        });
+        cached_entries.push(entry);
     }
 
-    Ok(links)
+    // Listings without validators can't be requested conditionally, so they aren't cached
+    if etag.is_some() || last_modified.is_some() {
+        listing.cache = Some(CachedListing {
+            etag,
+            last_modified,
+            entries: cached_entries,
+        });
+    }
+
+    Ok(listing)
+}
+
+/// Parses the listing page with the parser matching the server flavour.
+fn extract_entries(
+    content: &str,
+    url: &str,
+    server: Option<&str>,
+    content_type: Option<&str>,
+    flavour: ServerFlavour,
+) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>> {
+    trace!("Extracting entries from content");
+
+    let base = Url::parse(url)?;
+
+    // nginx `autoindex_format json` and `xml` listings are recognised by their content type,
+    // everything else is treated as an HTML listing
+    let media_type = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|media_type| media_type.trim().to_lowercase());
+    let parser: &dyn IndexParser = match media_type.as_deref() {
+        Some("application/json") => &NginxJsonParser,
+        Some("application/xml" | "text/xml") => &NginxXmlParser,
+        _ => parser_for(flavour, server, content),
+    };
+
+    parser.parse(content, &base)
+}
+
+/// The result of downloading a list of files.
+#[derive(Debug, Default)]
+pub struct DownloadReport {
+    /// Number of files per download outcome.
+    pub outcomes: BTreeMap<DownloadOutcome, usize>,
+    /// The files that failed to download, as crawled.
+    pub failed: Vec<(DownloadData, FailedDownload)>,
+    /// Number of bytes received from the server.
+    pub bytes_transferred: u64,
+    /// Number of downloaded files that matched their published checksum.
+    pub verified: usize,
+}
+
+/// Settings shared by all downloads of a run.
+#[derive(Debug)]
+pub struct DownloadOptions {
+    pub skip_policy: SkipPolicy,
+    pub retry: RetryConfig,
+    /// Published checksums the downloads are verified against.
+    pub checksums: ChecksumIndex,
+    /// What happens to downloads that don't match their checksum.
+    pub on_mismatch: MismatchAction,
+    /// Directory mismatched downloads are moved to with `MismatchAction::Quarantine`.
+    pub quarantine_dir: String,
+    /// The progress bars are added to, shared with the sites downloaded at the same time.
+    pub progress: MultiProgress,
 }
 
 /// Downloads files in parallel using async tasks.
@@ -204,8 +660,9 @@ pub async fn download_files_parallel(
     output_dir: &str,
     concurrent_downloads: usize,
     total_size: u64,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let multi_pb = Arc::new(MultiProgress::new());
+    options: DownloadOptions,
+) -> Result<DownloadReport, Box<dyn std::error::Error + Send + Sync>> {
+    let multi_pb = Arc::new(options.progress.clone());
     let overall_pb = multi_pb.add(ProgressBar::new(total_size));
     overall_pb.set_style(
         ProgressStyle::default_bar()
@@ -219,19 +676,27 @@ pub async fn download_files_parallel(
     // Limit the number of concurrent downloads
     let semaphore = Arc::new(Semaphore::new(concurrent_downloads));
     let total_size_downloaded = Arc::new(AtomicU64::new(0));
+    let bytes_transferred = Arc::new(AtomicU64::new(0));
 
     let mut tasks = Vec::new();
+    let mut originals = Vec::new();
 
     // We need to Arc the client to share it among tasks
     let client: Arc<Client> = Arc::new(client.clone());
+    let options = Arc::new(options);
 
     for mut file in files {
         let client = client.clone();
         let semaphore = semaphore.clone();
         let output_dir = output_dir.to_string();
         let total_size_downloaded = total_size_downloaded.clone();
+        let bytes_transferred = bytes_transferred.clone();
         let multi_pb = multi_pb.clone();
         let overall_pb = overall_pb.clone();
+        let options = options.clone();
+
+        // Keep the file as crawled, so failures can be written back to a crawl data file
+        originals.push(file.clone());
 
         // Rename the file to decode any percent-encoded characters
         file.output_dir = percent_decode_str(&file.output_dir)
@@ -239,7 +704,7 @@ pub async fn download_files_parallel(
             .into_owned();
 
         // Spawn a task for each file download
-        let task = tokio::spawn(async move {
+        let download = async move {
             let permit = semaphore.acquire().await.unwrap(); // Acquire a permit before starting
 
             // Create a progress bar for each file download
@@ -254,76 +719,777 @@ pub async fn download_files_parallel(
             file_pb.set_message(truncate_string(&file.output_dir, 70).to_string());
 
             // Download and save the file
-            match download_file(client, &file, &output_dir, &file_pb).await {
-                Ok(size) => {
+            let result = download_file(
+                client,
+                &file,
+                &output_dir,
+                &file_pb,
+                &options,
+                &bytes_transferred,
+            )
+            .await;
+            let outcome = match result {
+                Ok((size, outcome)) => {
                     total_size_downloaded.fetch_add(size, Ordering::SeqCst);
                     overall_pb.set_position(total_size_downloaded.load(Ordering::SeqCst));
                     file_pb.finish_and_clear();
+                    let verified =
+                        !outcome.is_skipped() && options.checksums.get(&file.output_dir).is_some();
+                    Ok((outcome, verified))
                 }
                 Err(e) => {
                     tracing::error!("Failed to download {}: {}", file, e);
                     file_pb.finish_and_clear();
+                    Err(FailedDownload {
+                        url: file.url.clone(),
+                        kind: e.kind(),
+                        status: e.status().map(|status| status.as_u16()),
+                        message: e.to_string(),
+                    })
                 }
-            }
+            };
 
             drop(permit); // Release the permit when done
-        });
+
+            outcome
+        };
+        let task = tokio::spawn(download.in_current_span());
 
         tasks.push(task);
     }
 
     // Wait for all tasks to complete
-    futures::future::join_all(tasks).await;
+    let results = futures::future::join_all(tasks).await;
 
     overall_pb.finish_with_message("All downloads complete!");
-    Ok(())
-}
 
-/// Downloads a file and saves it to the specified path, returning the size of the downloaded file.
+    // Count why files were skipped or (re-)downloaded, and collect the failures
+    let mut report = DownloadReport {
+        bytes_transferred: bytes_transferred.load(Ordering::SeqCst),
+        ..Default::default()
+    };
+    for (file, result) in originals.into_iter().zip(results) {
+        match result {
+            Ok(Ok((outcome, verified))) => {
+                *report.outcomes.entry(outcome).or_default() += 1;
+                if verified {
+                    report.verified += 1;
+                }
+            }
+            Ok(Err(failure)) => report.failed.push((file, failure)),
+            Err(e) => {
+                tracing::error!("Download task for {} failed: {}", file, e);
+                let failure = FailedDownload {
+                    url: file.url.clone(),
+                    kind: FailureKind::Panic,
+                    status: None,
+                    message: e.to_string(),
+                };
+                report.failed.push((file, failure));
+            }
+        }
+    }
+
+    Ok(report)
+}
 
 /// Downloads a file and saves it to the specified path, returning the size of the downloaded file.
+/// The bytes received from the server are added to `transferred`.
+///
+/// The data is written to a `.part` file which is renamed once the download completes. If a
+/// `.part` file is left over from an earlier run, the download resumes from its length with a
+/// `Range` request, provided the server answers with `206 Partial Content`.
+///
+/// Network errors and transient HTTP statuses are retried with exponential backoff. Every
+/// attempt resumes from the `.part` file, so a transfer that breaks off mid-stream continues
+/// from the last byte written.
+///
+/// If the file has a published checksum, it is hashed while streaming and verified before the
+/// `.part` file is renamed. A mismatch removes the downloaded data and counts as a failed
+/// attempt, so the file is downloaded again from scratch.
 pub async fn download_file(
     client: Arc<Client>,
     dload_file: &DownloadData,
     output_path: &str,
     pb: &ProgressBar,
-) -> Result<u64, Box<dyn std::error::Error>> {
-    // Check if the file already exists
-    if let Ok(metadata) =
-        tokio::fs::metadata(format!("{}/{}", output_path, dload_file.output_dir)).await
-    {
-        if metadata.is_file() {
-            debug!("Skipping existing file: {}", dload_file);
-            return Ok(metadata.len());
+    options: &DownloadOptions,
+    transferred: &AtomicU64,
+) -> Result<(u64, DownloadOutcome), DownloadError> {
+    let path = format!("{}/{}", output_path, dload_file.output_dir);
+    let part_path = format!("{}.part", path);
+
+    // Check if the file already exists, and whether the skip policy allows keeping it
+    let outcome = match tokio::fs::metadata(&path).await {
+        Ok(metadata) if metadata.is_file() => {
+            let modified = match options.skip_policy {
+                SkipPolicy::Newer => exact_modified_time(&client, dload_file).await,
+                _ => dload_file.modified,
+            };
+            let outcome = check_existing_file(options.skip_policy, dload_file, modified, &metadata);
+            if outcome.is_skipped() {
+                debug!("Skipping existing file ({}): {}", outcome, dload_file);
+                return Ok((metadata.len(), outcome));
+            }
+            outcome
         }
-    }
+        _ => DownloadOutcome::New,
+    };
 
-    // Send the GET request to download the file
-    let response = client.clone().get(dload_file.url.clone()).send().await?;
+    let retry = &options.retry;
+    let retry_strategy = ExponentialFactorBackoff::from_millis(retry.base_delay_ms, 2.0)
+        .max_delay_millis(retry.max_delay_ms)
+        .map(jitter)
+        .take(retry.attempts.saturating_sub(1));
+    let max_delay = Duration::from_millis(retry.max_delay_ms);
 
-    // Ensure the response is successful
-    if !response.status().is_success() {
-        return Err(format!("Failed to download file: {}", dload_file).into());
-    }
+    let client = &*client;
+    let (path, part_path) = (path.as_str(), part_path.as_str());
+
+    let size = RetryIf::spawn(
+        retry_strategy,
+        move || async move {
+            match transfer_file(
+                client,
+                dload_file,
+                path,
+                part_path,
+                pb,
+                options,
+                transferred,
+            )
+            .await
+            {
+                Ok(size) => Ok(size),
+                Err(e) => {
+                    // Wait as long as the server asked for on top of our own backoff
+                    if let DownloadError::Status {
+                        retry_after: Some(delay),
+                        ..
+                    } = e
+                    {
+                        tokio::time::sleep(delay.min(max_delay)).await;
+                    }
+                    Err(RetryError::transient(e))
+                }
+            }
+        },
+        |e: &DownloadError| e.is_transient(&retry.transient_status),
+        |e: &DownloadError, _| warn!("Retrying download of {}: {}", dload_file, e),
+    )
+    .await?;
+
+    debug!("Downloaded {} ({})", dload_file, outcome);
+
+    Ok((size, outcome))
+}
+
+/// Makes a single attempt at downloading the file, resuming from the `.part` file if present.
+async fn transfer_file(
+    client: &Client,
+    dload_file: &DownloadData,
+    path: &str,
+    part_path: &str,
+    pb: &ProgressBar,
+    options: &DownloadOptions,
+    transferred: &AtomicU64,
+) -> Result<u64, DownloadError> {
+    let expected = options.checksums.get(&dload_file.output_dir);
+    let mut hasher = expected.map(|checksum| Hasher::new(checksum.algorithm));
+
+    // Resume from the end of a partially downloaded file
+    let mut offset = match tokio::fs::metadata(&part_path).await {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => 0,
+    };
+
+    let (response, mut file) = loop {
+        let mut request = client.get(dload_file.url.clone());
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+            // Only resume if the file hasn't changed since the part was downloaded
+            if let Some(validator) = dload_file.range_validator() {
+                request = request.header(IF_RANGE, validator);
+            }
+        }
+
+        // Send the GET request to download the file
+        let response = request.send().await?;
+        let status = response.status();
+
+        if offset > 0 && status == StatusCode::PARTIAL_CONTENT {
+            if content_range_start(&response) == Some(offset) {
+                debug!("Resuming {} from byte {}", dload_file, offset);
+                if let Some(hasher) = &mut hasher {
+                    hasher.update_from_file(part_path).await?;
+                }
+                let file = OpenOptions::new().append(true).open(&part_path).await?;
+                break (response, file);
+            }
+            warn!(
+                "Unexpected Content-Range, restarting download of {}",
+                dload_file
+            );
+        } else if offset > 0 && status == StatusCode::RANGE_NOT_SATISFIABLE {
+            // The part file may already hold the complete file
+            if dload_file.size_exact && dload_file.size == Some(offset) {
+                if let (Some(checksum), Some(mut hasher)) = (expected, hasher) {
+                    hasher.update_from_file(part_path).await?;
+                    verify_checksum(dload_file, part_path, checksum, hasher, options).await?;
+                }
+                tokio::fs::rename(&part_path, &path).await?;
+                set_modified_time(path, dload_file.exact_modified())?;
+                debug!("Completed {} from its partial download", dload_file);
+                return Ok(offset);
+            }
+            warn!("Cannot resume {}, restarting download", dload_file);
+        } else if status.is_success() {
+            // The server ignored the range or the file changed, so start from scratch
+            if offset > 0 {
+                debug!("Server does not support resuming {}", dload_file);
+            }
+            offset = 0;
+            let file = File::create(&part_path).await?;
+            break (response, file);
+        } else {
+            return Err(DownloadError::Status {
+                status,
+                retry_after: retry_after(&response),
+            });
+        }
+
+        offset = 0;
+    };
+
+    // Keep the server's modification time, so the `Newer` skip policy can compare against it.
+    // Listing times may be off by the server's time zone, so they aren't used for this.
+    let modified = parse_last_modified(response.headers()).or(dload_file.exact_modified());
 
     // Get the total size of the file
-    let total_size = response.content_length().unwrap_or(0);
+    let total_size = offset + response.content_length().unwrap_or(0);
     pb.set_length(total_size);
-
-    // Open the output file
-    let mut file = File::create(format!("{}/{}", output_path, dload_file.output_dir)).await?;
+    pb.set_position(offset);
 
     // Write the content to the file in chunks
-    let mut downloaded_size = 0;
+    let mut downloaded_size = offset;
     let mut stream = response.bytes_stream();
     while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // Keep what was received so the next attempt can resume from it
+                file.flush().await?;
+                return Err(e.into());
+            }
+        };
         file.write_all(&chunk).await?;
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&chunk);
+        }
+        transferred.fetch_add(chunk.len() as u64, Ordering::Relaxed);
         downloaded_size += chunk.len() as u64;
         pb.set_position(downloaded_size);
     }
 
-    debug!("Downloaded {}", dload_file);
+    file.flush().await?;
+    drop(file);
+
+    if let (Some(checksum), Some(hasher)) = (expected, hasher) {
+        verify_checksum(dload_file, part_path, checksum, hasher, options).await?;
+    }
+
+    // Move the completed download into place
+    tokio::fs::rename(&part_path, &path).await?;
+    set_modified_time(path, modified)?;
 
     Ok(downloaded_size)
 }
+
+/// Compares the digest of a completed download with its published checksum.
+///
+/// On a mismatch the `.part` file is deleted or quarantined, so the next attempt downloads the
+/// file from scratch.
+async fn verify_checksum(
+    dload_file: &DownloadData,
+    part_path: &str,
+    expected: &Checksum,
+    hasher: Hasher,
+    options: &DownloadOptions,
+) -> Result<(), DownloadError> {
+    let actual = hasher.finalize();
+    if actual == expected.digest {
+        debug!("Verified checksum of {}", dload_file);
+        return Ok(());
+    }
+
+    match options.on_mismatch {
+        MismatchAction::Delete => tokio::fs::remove_file(part_path).await?,
+        MismatchAction::Quarantine => {
+            let quarantine_path = Path::new(&options.quarantine_dir).join(&dload_file.output_dir);
+            move_file(Path::new(part_path), &quarantine_path).await?;
+            warn!(
+                "Moved mismatched download of {} to {}",
+                dload_file,
+                quarantine_path.display()
+            );
+        }
+    }
+
+    Err(DownloadError::Checksum {
+        expected: expected.clone(),
+        actual,
+    })
+}
+
+/// An error that stopped a file download.
+#[derive(Debug)]
+pub enum DownloadError {
+    /// The server answered with an error status.
+    Status {
+        status: StatusCode,
+        /// How long the server asked us to wait in its `Retry-After` header.
+        retry_after: Option<Duration>,
+    },
+    /// The request failed or the connection broke off.
+    Network(reqwest::Error),
+    /// The file couldn't be written.
+    Io(std::io::Error),
+    /// The downloaded data doesn't match the published checksum.
+    Checksum { expected: Checksum, actual: String },
+}
+
+impl DownloadError {
+    /// Whether another attempt at the download might succeed.
+    pub fn is_transient(&self, transient_status: &[u16]) -> bool {
+        match self {
+            DownloadError::Status { status, .. } => transient_status.contains(&status.as_u16()),
+            DownloadError::Network(e) => !(e.is_builder() || e.is_redirect()),
+            DownloadError::Io(_) => false,
+            DownloadError::Checksum { .. } => true,
+        }
+    }
+
+    /// The kind of failure, as recorded in failures files.
+    pub fn kind(&self) -> FailureKind {
+        match self {
+            DownloadError::Status { .. } => FailureKind::Status,
+            DownloadError::Network(_) => FailureKind::Network,
+            DownloadError::Io(_) => FailureKind::Io,
+            DownloadError::Checksum { .. } => FailureKind::Checksum,
+        }
+    }
+
+    /// The HTTP status the server answered with, if any.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            DownloadError::Status { status, .. } => Some(*status),
+            DownloadError::Network(e) => e.status(),
+            DownloadError::Io(_) | DownloadError::Checksum { .. } => None,
+        }
+    }
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Status { status, .. } => write!(f, "server returned {}", status),
+            DownloadError::Network(e) => write!(f, "network error: {}", e),
+            DownloadError::Io(e) => write!(f, "I/O error: {}", e),
+            DownloadError::Checksum { expected, actual } => {
+                write!(
+                    f,
+                    "checksum mismatch (expected {}, got {})",
+                    expected, actual
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DownloadError::Status { .. } | DownloadError::Checksum { .. } => None,
+            DownloadError::Network(e) => Some(e),
+            DownloadError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(e: reqwest::Error) -> Self {
+        DownloadError::Network(e)
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(e: std::io::Error) -> Self {
+        DownloadError::Io(e)
+    }
+}
+
+/// What happened to a file in the download list, and why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum DownloadOutcome {
+    /// Downloaded because it didn't exist locally.
+    New,
+    /// Re-downloaded because its size differs from the crawled size.
+    SizeMismatch,
+    /// Re-downloaded because the server's copy is newer.
+    Newer,
+    /// Re-downloaded because the skip policy is `Always`.
+    Forced,
+    /// Skipped because it exists locally.
+    Exists,
+    /// Skipped because its size matches the crawled size.
+    SizeMatches,
+    /// Skipped because the local copy is at least as new as the server's.
+    UpToDate,
+    /// Skipped because the crawled size or modification time the policy needs is unknown.
+    Unverifiable,
+}
+
+impl DownloadOutcome {
+    /// Returns true if the file was left untouched.
+    pub fn is_skipped(&self) -> bool {
+        matches!(
+            self,
+            DownloadOutcome::Exists
+                | DownloadOutcome::SizeMatches
+                | DownloadOutcome::UpToDate
+                | DownloadOutcome::Unverifiable
+        )
+    }
+}
+
+impl Display for DownloadOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            DownloadOutcome::New => "Downloaded (new file)",
+            DownloadOutcome::SizeMismatch => "Re-downloaded (size differs)",
+            DownloadOutcome::Newer => "Re-downloaded (newer on server)",
+            DownloadOutcome::Forced => "Re-downloaded (always)",
+            DownloadOutcome::Exists => "Skipped (exists)",
+            DownloadOutcome::SizeMatches => "Skipped (size matches)",
+            DownloadOutcome::UpToDate => "Skipped (up to date)",
+            DownloadOutcome::Unverifiable => "Skipped (no size or date to compare)",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// Decides whether an existing local file can be kept under the skip policy, given the
+/// modification time of the remote file.
+fn check_existing_file(
+    skip_policy: SkipPolicy,
+    dload_file: &DownloadData,
+    modified: Option<DateTime<Utc>>,
+    metadata: &std::fs::Metadata,
+) -> DownloadOutcome {
+    match skip_policy {
+        SkipPolicy::Exists => DownloadOutcome::Exists,
+        SkipPolicy::Always => DownloadOutcome::Forced,
+        SkipPolicy::SizeMatch => match dload_file.size {
+            None => DownloadOutcome::Unverifiable,
+            Some(size) if dload_file.size_matches(metadata.len()) => {
+                trace!("Size {} matches for {}", size, dload_file);
+                DownloadOutcome::SizeMatches
+            }
+            Some(_) => DownloadOutcome::SizeMismatch,
+        },
+        SkipPolicy::Newer => match (modified, metadata.modified()) {
+            (Some(remote), Ok(local)) => {
+                if remote > DateTime::<Utc>::from(local) {
+                    DownloadOutcome::Newer
+                } else {
+                    DownloadOutcome::UpToDate
+                }
+            }
+            _ => DownloadOutcome::Unverifiable,
+        },
+    }
+}
+
+/// Returns the exact modification time of the remote file, for the `Newer` skip policy.
+///
+/// Times read from a listing page may be off by the server's time zone, so the time is
+/// requested with a HEAD request instead if the crawl only found such a time.
+async fn exact_modified_time(client: &Client, dload_file: &DownloadData) -> Option<DateTime<Utc>> {
+    if dload_file.modified_exact {
+        return dload_file.modified;
+    }
+
+    let url = Url::parse(&dload_file.url).ok()?;
+    match get_file_metadata(client, &url).await {
+        Ok(metadata) => metadata.modified,
+        Err(e) => {
+            warn!(
+                "Failed to get the modification time of {}: {}",
+                dload_file, e
+            );
+            None
+        }
+    }
+}
+
+/// Sets the modification time of a downloaded file.
+fn set_modified_time(path: &str, modified: Option<DateTime<Utc>>) -> std::io::Result<()> {
+    if let Some(modified) = modified {
+        std::fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(modified.into())?;
+    }
+    Ok(())
+}
+
+/// Returns the first byte position of a `206 Partial Content` response.
+fn content_range_start(response: &Response) -> Option<u64> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let range = value.strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+/// Reads a `Retry-After` header given in seconds.
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use indicatif::ProgressDrawTarget;
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    /// Starts a server answering one request per connection with the next of the raw
+    /// `responses`, then closing the connection. Returns the URL of the server and the heads of
+    /// the requests received so far, lowercased.
+    async fn mock_server(responses: Vec<&'static str>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let head = read_request(&mut stream).await;
+                received.lock().unwrap().push(head);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (server, requests)
+    }
+
+    /// Reads the request line and headers of a request, lowercased.
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert_ne!(read, 0, "connection closed before the end of the request");
+            request.extend_from_slice(&buffer[..read]);
+        }
+        String::from_utf8_lossy(&request).to_lowercase()
+    }
+
+    /// Downloads the 11 byte `file.txt` from the server into `dir`, returning the downloaded size.
+    async fn download(server: &str, dir: &Path) -> Result<u64, DownloadError> {
+        let file = DownloadData {
+            url: format!("{}file.txt", server),
+            output_dir: "file.txt".to_string(),
+            size: Some(11),
+            size_exact: true,
+            modified: None,
+            modified_exact: false,
+            etag: None,
+        };
+
+        let options = DownloadOptions {
+            skip_policy: SkipPolicy::Exists,
+            retry: RetryConfig {
+                attempts: 3,
+                base_delay_ms: 1,
+                max_delay_ms: 1,
+                ..Default::default()
+            },
+            checksums: ChecksumIndex::default(),
+            on_mismatch: MismatchAction::default(),
+            quarantine_dir: String::new(),
+            progress: MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        };
+
+        let (size, _) = download_file(
+            Arc::new(Client::new()),
+            &file,
+            dir.to_str().unwrap(),
+            &ProgressBar::hidden(),
+            &options,
+            &AtomicU64::new(0),
+        )
+        .await?;
+        Ok(size)
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_part_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file.txt.part"), "hello ").unwrap();
+        let (server, requests) = mock_server(vec![
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 6-10/11\r\nContent-Length: 5\r\nConnection: close\r\n\r\nworld",
+        ])
+        .await;
+
+        assert_eq!(download(&server, dir.path()).await.unwrap(), 11);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("file.txt")).unwrap(),
+            "hello world"
+        );
+        assert!(!dir.path().join("file.txt.part").exists());
+        assert!(requests.lock().unwrap()[0].contains("\r\nrange: bytes=6-\r\n"));
+    }
+
+    #[tokio::test]
+    async fn restarts_when_the_range_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file.txt.part"), "stale").unwrap();
+        let (server, _) = mock_server(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 11\r\nConnection: close\r\n\r\nhello world",
+        ])
+        .await;
+
+        assert_eq!(download(&server, dir.path()).await.unwrap(), 11);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("file.txt")).unwrap(),
+            "hello world"
+        );
+    }
+
+    #[tokio::test]
+    async fn completes_a_part_file_holding_the_whole_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file.txt.part"), "hello world").unwrap();
+        let (server, requests) = mock_server(vec![
+            "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */11\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ])
+        .await;
+
+        assert_eq!(download(&server, dir.path()).await.unwrap(), 11);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("file.txt")).unwrap(),
+            "hello world"
+        );
+        assert!(!dir.path().join("file.txt.part").exists());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_from_where_the_transfer_broke_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, requests) = mock_server(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 11\r\nConnection: close\r\n\r\nhello ",
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 6-10/11\r\nContent-Length: 5\r\nConnection: close\r\n\r\nworld",
+        ])
+        .await;
+
+        assert_eq!(download(&server, dir.path()).await.unwrap(), 11);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("file.txt")).unwrap(),
+            "hello world"
+        );
+
+        let requests = requests.lock().unwrap();
+        assert!(!requests[0].contains("\r\nrange:"));
+        assert!(requests[1].contains("\r\nrange: bytes=6-\r\n"));
+    }
+
+    #[tokio::test]
+    async fn records_directories_that_fail_to_list() {
+        let (server, requests) = mock_server(vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 107\r\nConnection: close\r\n\r\n[{\"name\":\"a\",\"type\":\"directory\"},{\"name\":\"b\",\"type\":\"directory\"},{\"name\":\"top.txt\",\"type\":\"file\",\"size\":5}]",
+            "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 41\r\nConnection: close\r\n\r\n[{\"name\":\"c.txt\",\"type\":\"file\",\"size\":3}]",
+        ])
+        .await;
+
+        let crawler = Crawler {
+            client: Client::new(),
+            source: Arc::new(ListingSource::Index(ServerFlavour::Auto)),
+            filters: Arc::from([]),
+            concurrency: 1,
+            order: CrawlOrder::BreadthFirst,
+            pb: ProgressBar::hidden(),
+            checkpoint_path: None,
+            checkpoint_interval: Duration::from_secs(60),
+            cache: Arc::new(HashMap::new()),
+        };
+        let crawl_data = crawler.crawl(server.clone()).await.unwrap();
+
+        let paths: Vec<_> = crawl_data
+            .download_list
+            .iter()
+            .map(|file| file.output_dir.as_str())
+            .collect();
+        assert_eq!(paths, ["top.txt", "b/c.txt"]);
+        assert_eq!(crawl_data.total_size, 8);
+
+        assert_eq!(crawl_data.failed_directories.len(), 1);
+        assert_eq!(
+            crawl_data.failed_directories[0].url,
+            format!("{}a/", server)
+        );
+        assert_eq!(crawl_data.failed_directories[0].path, "a");
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[1].starts_with("get /a/ http/1.1\r\n"));
+        assert!(requests[2].starts_with("get /b/ http/1.1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn requests_the_exact_time_of_listing_times() {
+        let (server, requests) = mock_server(vec![
+            "HTTP/1.1 200 OK\r\nLast-Modified: Fri, 15 Nov 2024 10:21:00 GMT\r\nContent-Length: 11\r\nConnection: close\r\n\r\n",
+        ])
+        .await;
+        let time = |time| Some(DateTime::parse_from_rfc3339(time).unwrap().to_utc());
+
+        let mut file = DownloadData {
+            url: format!("{}file.txt", server),
+            output_dir: "file.txt".to_string(),
+            size: Some(11),
+            size_exact: true,
+            modified: time("2024-11-15T11:21:00Z"),
+            modified_exact: false,
+            etag: None,
+        };
+        let client = Client::new();
+
+        // The listing time is an hour off, as the server printed its local time
+        assert_eq!(
+            exact_modified_time(&client, &file).await,
+            time("2024-11-15T10:21:00Z")
+        );
+        assert!(requests.lock().unwrap()[0].starts_with("head /file.txt http/1.1\r\n"));
+
+        file.modified_exact = true;
+        assert_eq!(
+            exact_modified_time(&client, &file).await,
+            time("2024-11-15T11:21:00Z")
+        );
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,291 @@
+use chrono::{DateTime, Utc};
+use percent_encoding::percent_decode_str;
+use reqwest::{Client, Url};
+use tracing::trace;
+
+use crate::{
+    index_parser::{named_entry, ListingEntry},
+    network::fetch_url,
+    xml::XmlElement,
+};
+
+/// Lists the objects and common prefixes directly below the directory at `url`, following
+/// continuation tokens until the listing is complete.
+///
+/// The directory URL is the bucket URL followed by the key prefix, e.g.
+/// `https://minio.local/bucket/photos/2024/` lists the `photos/2024/` prefix.
+pub async fn list_prefix(
+    client: &Client,
+    bucket_url: &Url,
+    url: &Url,
+) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>> {
+    let prefix = url
+        .path()
+        .strip_prefix(bucket_url.path())
+        .ok_or_else(|| format!("{} is not inside the bucket {}", url, bucket_url))?;
+    let prefix = percent_decode_str(prefix).decode_utf8()?.into_owned();
+
+    let mut entries = Vec::new();
+    let mut continuation_token: Option<String> = None;
+
+    loop {
+        let mut request_url = bucket_url.clone();
+        {
+            let mut query = request_url.query_pairs_mut();
+            query
+                .append_pair("list-type", "2")
+                .append_pair("prefix", &prefix)
+                .append_pair("delimiter", "/");
+            if let Some(token) = &continuation_token {
+                query.append_pair("continuation-token", token);
+            }
+        }
+
+        trace!("Listing bucket: {}", request_url);
+
+        let response = fetch_url(client, request_url.as_str()).await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            let error = XmlElement::parse(&body)
+                .ok()
+                .and_then(|error| error.child_text("Message").map(str::to_owned))
+                .unwrap_or_else(|| status.to_string());
+            return Err(format!("Failed to list bucket at {}: {}", request_url, error).into());
+        }
+
+        let result = XmlElement::parse(&body)?;
+        if result.name != "ListBucketResult" {
+            return Err(format!(
+                "Unexpected response from {}: <{}>",
+                request_url, result.name
+            )
+            .into());
+        }
+
+        for prefix_element in result.children_named("CommonPrefixes") {
+            let Some(name) = prefix_element
+                .child_text("Prefix")
+                .and_then(|common_prefix| common_prefix.strip_prefix(prefix.as_str()))
+            else {
+                continue;
+            };
+
+            if let Some(entry) = named_entry(url, name.trim_end_matches('/'), true) {
+                entries.push(entry);
+            }
+        }
+
+        for object in result.children_named("Contents") {
+            // Skip the zero-byte "folder" objects some tools create for the prefix itself
+            let Some(name) = object
+                .child_text("Key")
+                .and_then(|key| key.strip_prefix(prefix.as_str()))
+            else {
+                continue;
+            };
+
+            let Some(mut entry) = named_entry(url, name, false) else {
+                continue;
+            };
+
+            entry.size = object.child_text("Size").and_then(|size| size.parse().ok());
+            entry.size_exact = entry.size.is_some();
+            entry.mtime = object
+                .child_text("LastModified")
+                .and_then(|modified| DateTime::parse_from_rfc3339(modified).ok())
+                .map(|modified| modified.with_timezone(&Utc));
+            entry.mtime_exact = entry.mtime.is_some();
+            entry.etag = object.child_text("ETag").map(str::to_owned);
+
+            entries.push(entry);
+        }
+
+        let is_truncated = result.child_text("IsTruncated") == Some("true");
+        continuation_token = result
+            .child_text("NextContinuationToken")
+            .map(str::to_owned);
+
+        if !is_truncated || continuation_token.is_none() {
+            break;
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Starts a server answering every request with the status and body `respond` returns for
+    /// its query string. Returns the bucket URL and the queries received so far.
+    async fn mock_bucket(respond: fn(&str) -> (u16, String)) -> (Url, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bucket_url = Url::parse(&format!(
+            "http://{}/bucket/",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let queries = Arc::new(Mutex::new(Vec::new()));
+
+        let received = queries.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+
+                let request = String::from_utf8_lossy(&request);
+                let target = request.split(' ').nth(1).unwrap_or_default();
+                let query = target.split_once('?').map_or("", |(_, query)| query);
+                received.lock().unwrap().push(query.to_string());
+
+                let (status, body) = respond(query);
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (bucket_url, queries)
+    }
+
+    #[tokio::test]
+    async fn lists_prefix_across_pages() {
+        let (bucket_url, queries) = mock_bucket(|query| {
+            let body = if query.contains("continuation-token=") {
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Name>bucket</Name>
+  <Prefix>photos/2024/</Prefix>
+  <KeyCount>1</KeyCount>
+  <MaxKeys>2</MaxKeys>
+  <Delimiter>/</Delimiter>
+  <IsTruncated>false</IsTruncated>
+  <Contents>
+    <Key>photos/2024/c.jpg</Key>
+    <LastModified>2024-11-15T10:22:00.000Z</LastModified>
+    <ETag>"c0ffee"</ETag>
+    <Size>300</Size>
+    <StorageClass>STANDARD</StorageClass>
+  </Contents>
+</ListBucketResult>"#
+            } else {
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Name>bucket</Name>
+  <Prefix>photos/2024/</Prefix>
+  <KeyCount>3</KeyCount>
+  <MaxKeys>3</MaxKeys>
+  <Delimiter>/</Delimiter>
+  <IsTruncated>true</IsTruncated>
+  <NextContinuationToken>page/2=</NextContinuationToken>
+  <Contents>
+    <Key>photos/2024/</Key>
+    <LastModified>2024-11-15T10:20:00.000Z</LastModified>
+    <Size>0</Size>
+  </Contents>
+  <Contents>
+    <Key>photos/2024/a b.jpg</Key>
+    <LastModified>2024-11-15T10:21:00.000Z</LastModified>
+    <ETag>"d41d8cd9"</ETag>
+    <Size>100</Size>
+    <StorageClass>STANDARD</StorageClass>
+  </Contents>
+  <CommonPrefixes>
+    <Prefix>photos/2024/raw/</Prefix>
+  </CommonPrefixes>
+</ListBucketResult>"#
+            };
+            (200, body.to_string())
+        })
+        .await;
+
+        let url = bucket_url.join("photos/2024/").unwrap();
+        let entries = list_prefix(&Client::new(), &bucket_url, &url)
+            .await
+            .unwrap();
+
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["raw", "a%20b.jpg", "c.jpg"]);
+
+        assert!(entries[0].is_dir);
+        assert_eq!(
+            entries[0].url.as_str(),
+            format!("{}photos/2024/raw/", bucket_url)
+        );
+
+        assert!(!entries[1].is_dir);
+        assert_eq!(entries[1].size, Some(100));
+        assert!(entries[1].size_exact);
+        assert_eq!(entries[1].etag.as_deref(), Some("\"d41d8cd9\""));
+        assert_eq!(
+            entries[1].mtime,
+            Some(
+                DateTime::parse_from_rfc3339("2024-11-15T10:21:00Z")
+                    .unwrap()
+                    .with_timezone(&Utc)
+            )
+        );
+
+        assert_eq!(entries[2].size, Some(300));
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(
+            *queries,
+            [
+                "list-type=2&prefix=photos%2F2024%2F&delimiter=%2F",
+                "list-type=2&prefix=photos%2F2024%2F&delimiter=%2F&continuation-token=page%2F2%3D",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_bucket_errors() {
+        let (bucket_url, _) = mock_bucket(|_| {
+            (
+                403,
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>"#
+                    .to_string(),
+            )
+        })
+        .await;
+
+        let error = list_prefix(&Client::new(), &bucket_url, &bucket_url)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().ends_with(": Access Denied"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn rejects_urls_outside_the_bucket() {
+        let bucket_url = Url::parse("http://127.0.0.1:9/bucket/").unwrap();
+        let url = Url::parse("http://127.0.0.1:9/other/").unwrap();
+
+        assert!(list_prefix(&Client::new(), &bucket_url, &url)
+            .await
+            .is_err());
+    }
+}
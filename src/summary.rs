@@ -0,0 +1,201 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{
+    config::SkipPolicy,
+    network::{DownloadOutcome, DownloadReport},
+    utils::format_size,
+};
+
+/// How a run ended, reported as the process exit code so scripts can tell the cases apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ExitStatus {
+    /// Every file was downloaded or skipped.
+    Success,
+    /// The run was aborted by an unexpected error.
+    Error,
+    /// Some files failed to download.
+    PartialFailure,
+    /// The crawl failed, so nothing was downloaded.
+    CrawlFailure,
+    /// The configuration is missing or invalid.
+    ConfigError,
+    /// The local mirror doesn't match the crawl data.
+    MirrorMismatch,
+}
+
+impl ExitStatus {
+    /// The process exit code for this status.
+    pub fn code(self) -> i32 {
+        match self {
+            ExitStatus::Success => 0,
+            ExitStatus::Error => 1,
+            ExitStatus::PartialFailure => 2,
+            ExitStatus::CrawlFailure => 3,
+            ExitStatus::ConfigError => 4,
+            ExitStatus::MirrorMismatch => 5,
+        }
+    }
+}
+
+/// An error that ends the run of a site early, with the status it is reported with.
+#[derive(Debug)]
+pub struct RunError {
+    pub status: ExitStatus,
+    pub message: String,
+}
+
+impl RunError {
+    pub fn new(status: ExitStatus, message: String) -> Self {
+        Self { status, message }
+    }
+
+    /// Returns the status of an error that ended a run, which is `Error` unless it is a
+    /// `RunError`.
+    pub fn status_of(error: &(dyn std::error::Error + Send + Sync + 'static)) -> ExitStatus {
+        error
+            .downcast_ref::<RunError>()
+            .map_or(ExitStatus::Error, |error| error.status)
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RunError {}
+
+/// The end-of-run summary, logged when the program finishes and optionally written as a JSON
+/// report.
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub status: ExitStatus,
+    pub exit_code: i32,
+    /// Number of files that were downloaded, including re-downloads.
+    pub downloaded: usize,
+    /// Number of files that were skipped because they already exist locally.
+    pub skipped: usize,
+    /// Number of files that failed to download.
+    pub failed: usize,
+    /// Number of downloaded files that matched their published checksum.
+    pub verified: usize,
+    /// Number of bytes received from the server.
+    pub bytes_transferred: u64,
+    pub elapsed_secs: f64,
+    /// Average download speed in bytes per second.
+    pub bytes_per_sec: f64,
+    pub skip_policy: Option<SkipPolicy>,
+    /// Number of files per download outcome.
+    pub outcomes: BTreeMap<DownloadOutcome, usize>,
+    /// The error that ended the run early, if any.
+    pub error: Option<String>,
+    pub finished_at: DateTime<Utc>,
+}
+
+impl RunSummary {
+    /// Summarises a completed download run.
+    pub fn from_report(
+        report: &DownloadReport,
+        skip_policy: SkipPolicy,
+        elapsed: Duration,
+    ) -> Self {
+        let skipped = report
+            .outcomes
+            .iter()
+            .filter(|(outcome, _)| outcome.is_skipped())
+            .map(|(_, count)| count)
+            .sum();
+        let downloaded = report.outcomes.values().sum::<usize>() - skipped;
+
+        let status = if report.failed.is_empty() {
+            ExitStatus::Success
+        } else {
+            ExitStatus::PartialFailure
+        };
+
+        Self {
+            status,
+            exit_code: status.code(),
+            downloaded,
+            skipped,
+            failed: report.failed.len(),
+            verified: report.verified,
+            bytes_transferred: report.bytes_transferred,
+            elapsed_secs: elapsed.as_secs_f64(),
+            bytes_per_sec: throughput(report.bytes_transferred, elapsed),
+            skip_policy: Some(skip_policy),
+            outcomes: report.outcomes.clone(),
+            error: None,
+            finished_at: Utc::now(),
+        }
+    }
+
+    /// Summarises a run that ended before any file was downloaded.
+    pub fn aborted(status: ExitStatus, error: String, elapsed: Duration) -> Self {
+        Self {
+            status,
+            exit_code: status.code(),
+            downloaded: 0,
+            skipped: 0,
+            failed: 0,
+            verified: 0,
+            bytes_transferred: 0,
+            elapsed_secs: elapsed.as_secs_f64(),
+            bytes_per_sec: 0.0,
+            skip_policy: None,
+            outcomes: BTreeMap::new(),
+            error: Some(error),
+            finished_at: Utc::now(),
+        }
+    }
+
+    /// Logs the summary of a completed download run.
+    pub fn log(&self) {
+        if let Some(skip_policy) = self.skip_policy {
+            info!("Skip policy: {:?}", skip_policy);
+        }
+        for (outcome, count) in &self.outcomes {
+            info!("{}: {}", outcome, count);
+        }
+
+        let summary = format!(
+            "Summary: {} downloaded, {} skipped, {} failed",
+            self.downloaded, self.skipped, self.failed
+        );
+        if self.failed > 0 {
+            error!("{}", summary);
+        } else {
+            info!("{}", summary);
+        }
+        if self.verified > 0 {
+            info!("Verified the checksums of {} files", self.verified);
+        }
+        info!(
+            "Transferred {} in {:.1}s ({}/s)",
+            format_size(self.bytes_transferred),
+            self.elapsed_secs,
+            format_size(self.bytes_per_sec as u64)
+        );
+    }
+
+    /// Writes the summary to the file as JSON.
+    pub fn write(&self, path: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Returns the average speed in bytes per second.
+fn throughput(bytes: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        bytes as f64 / secs
+    } else {
+        0.0
+    }
+}
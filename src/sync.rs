@@ -0,0 +1,405 @@
+use std::{collections::HashSet, path::Path};
+
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use tracing::{info, warn};
+
+use crate::{
+    config::FilterRule,
+    crawl_data::CrawlData,
+    utils::{encode_path, move_file, should_filter, walk_directories, walk_files},
+};
+
+/// The local files and directories that are no longer present upstream.
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    /// Files to delete, relative to the output directory.
+    pub files: Vec<String>,
+    /// Directories to delete once they are empty, relative to the output directory and ordered
+    /// deepest first.
+    pub directories: Vec<String>,
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.directories.is_empty()
+    }
+
+    /// Returns true if the plan deletes more files than `--max-delete` allows. Directories
+    /// aren't counted, as only empty ones are removed.
+    pub fn exceeds_max_delete(&self, max_delete: usize) -> bool {
+        self.files.len() > max_delete
+    }
+}
+
+/// Compares the output directory with the crawl data and finds what has to be deleted to make
+/// the mirror match it.
+///
+/// The crawl data has to come from a complete crawl, as any file missing from it is deleted. A
+/// failures file, which only lists some of the files, is refused here. The local files below
+/// the directories the crawl couldn't list are kept, as nothing is known about them.
+///
+/// Local files the filters exclude are kept, as they weren't crawled and may well still be
+/// upstream. Their URLs are found by joining their paths to `root_url`, the URL the crawl
+/// started from.
+///
+/// Partial downloads of crawled files are kept, so they can still be resumed. Anything inside
+/// `keep_dir` (like a trash directory within the output directory) is left alone.
+pub fn plan_sync(
+    crawl_data: &CrawlData,
+    output_dir: &str,
+    keep_dir: Option<&str>,
+    root_url: &Url,
+    filters: &[FilterRule],
+) -> Result<SyncPlan, Box<dyn std::error::Error + Send + Sync>> {
+    if !crawl_data.failures.is_empty() {
+        return Err(
+            "The crawl data only lists failed downloads, not a complete crawl, so nothing can be deleted based on it"
+                .into(),
+        );
+    }
+
+    if !Path::new(output_dir).is_dir() {
+        return Ok(SyncPlan::default());
+    }
+
+    let decode = |path: &str| percent_decode_str(path).decode_utf8_lossy().into_owned();
+
+    let upstream_files: HashSet<String> = crawl_data
+        .download_list
+        .iter()
+        .map(|file| decode(&file.output_dir))
+        .collect();
+
+    // Directories are upstream if they were crawled, or if they hold a crawled file
+    let mut upstream_directories: HashSet<String> = crawl_data
+        .directories_to_create
+        .iter()
+        .map(|directory| decode(directory.trim_end_matches('/')))
+        .collect();
+    for file in &upstream_files {
+        let mut path = file.as_str();
+        while let Some((parent, _)) = path.rsplit_once('/') {
+            upstream_directories.insert(parent.to_string());
+            path = parent;
+        }
+    }
+
+    if !crawl_data.failed_directories.is_empty() {
+        warn!(
+            "Keeping the local files below the {} directories that couldn't be listed",
+            crawl_data.failed_directories.len()
+        );
+    }
+
+    let kept_prefixes: Vec<String> = keep_dir
+        .and_then(|keep_dir| relative_to(output_dir, keep_dir))
+        .into_iter()
+        .chain(
+            crawl_data
+                .failed_directories
+                .iter()
+                .map(|directory| decode(&directory.path)),
+        )
+        .map(|directory| format!("{}/", directory))
+        .collect();
+    let is_kept = |path: &str| {
+        kept_prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix) || path == prefix.trim_end_matches('/'))
+    };
+
+    let mut plan = SyncPlan::default();
+
+    for path in walk_files(output_dir)?.into_keys() {
+        let is_partial = path
+            .strip_suffix(".part")
+            .is_some_and(|target| upstream_files.contains(target));
+        if !upstream_files.contains(&path)
+            && !is_partial
+            && !is_kept(&path)
+            && !is_excluded(root_url, filters, &path, false)
+        {
+            plan.files.push(path);
+        }
+    }
+
+    for path in walk_directories(output_dir)? {
+        if !upstream_directories.contains(&path)
+            && !is_kept(&path)
+            && !is_excluded(root_url, filters, &path, true)
+        {
+            plan.directories.push(path);
+        }
+    }
+
+    plan.files.sort();
+    plan.directories
+        .sort_by_key(|directory| std::cmp::Reverse(directory.matches('/').count()));
+
+    Ok(plan)
+}
+
+/// Returns true if the filters exclude the local path (relative to the output directory) from
+/// the crawl, or any of the directories it is in, which the crawl then never listed.
+fn is_excluded(root_url: &Url, filters: &[FilterRule], path: &str, is_dir: bool) -> bool {
+    let is_filtered = |path: &str| {
+        root_url
+            .join(&encode_path(path))
+            .is_ok_and(|url| should_filter(url.path(), filters).unwrap_or(false))
+    };
+
+    // Directories are filtered with their trailing slash, as they are listed
+    let mut ancestor = String::new();
+    let mut segments = path.split('/').peekable();
+    while let Some(segment) = segments.next() {
+        ancestor.push_str(segment);
+        if segments.peek().is_some() || is_dir {
+            ancestor.push('/');
+        }
+        if is_filtered(&ancestor) {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Deletes the files and directories of the plan, or moves the files to `trash_dir` keeping
+/// their relative paths. Returns the number of files and directories removed.
+pub async fn apply_sync(
+    plan: &SyncPlan,
+    output_dir: &str,
+    trash_dir: Option<&str>,
+) -> Result<(usize, usize), Box<dyn std::error::Error + Send + Sync>> {
+    let mut removed_files = 0;
+    for file in &plan.files {
+        let path = Path::new(output_dir).join(file);
+        let result = match trash_dir {
+            Some(trash_dir) => move_file(&path, &Path::new(trash_dir).join(file)).await,
+            None => tokio::fs::remove_file(&path).await,
+        };
+
+        match result {
+            Ok(()) => {
+                info!("Deleted {}", file);
+                removed_files += 1;
+            }
+            Err(e) => warn!("Failed to delete {}: {}", path.display(), e),
+        }
+    }
+
+    // The directories are ordered deepest first, so children are removed before their parents
+    let mut removed_directories = 0;
+    for directory in &plan.directories {
+        let path = Path::new(output_dir).join(directory);
+        match tokio::fs::remove_dir(&path).await {
+            Ok(()) => removed_directories += 1,
+            Err(e) => warn!("Failed to remove directory {}: {}", path.display(), e),
+        }
+    }
+
+    Ok((removed_files, removed_directories))
+}
+
+/// Returns the path of `path` relative to `base`, if it lies within it.
+fn relative_to(base: &str, path: &str) -> Option<String> {
+    let base = std::fs::canonicalize(base).ok()?;
+    let path = std::fs::canonicalize(path).ok()?;
+    let relative = path.strip_prefix(base).ok()?;
+    Some(
+        relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        config::RuleType,
+        crawl_data::{DownloadData, FailedDirectory, FailedDownload, FailureKind},
+    };
+
+    /// The URL the crawl started from.
+    fn root_url() -> Url {
+        Url::parse("http://127.0.0.1/pub/").unwrap()
+    }
+
+    /// Builds the crawl data of a complete crawl, which found the files and directories given
+    /// relative to the output directory.
+    fn crawl_data(files: &[&str], directories: &[&str]) -> CrawlData {
+        CrawlData {
+            download_list: files
+                .iter()
+                .map(|file| DownloadData {
+                    url: format!("{}{}", root_url(), file),
+                    output_dir: file.to_string(),
+                    size: None,
+                    size_exact: false,
+                    modified: None,
+                    modified_exact: false,
+                    etag: None,
+                })
+                .collect(),
+            total_size: 0,
+            directories_to_create: directories
+                .iter()
+                .map(|directory| format!("{}/", directory))
+                .collect(),
+            saved_at: Utc::now(),
+            failures: Vec::new(),
+            listings: HashMap::new(),
+            failed_directories: Vec::new(),
+        }
+    }
+
+    /// Creates the files below `dir`, along with their parent directories.
+    fn create_files(dir: &Path, files: &[&str]) {
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn deletes_what_is_no_longer_upstream() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().to_str().unwrap();
+        let trash_dir = dir.path().join(".trash");
+        create_files(
+            dir.path(),
+            &[
+                "docs/a.txt",
+                "docs/old.txt",
+                "b.iso.part",
+                "stale.part",
+                "gone/deeper/c.txt",
+                ".trash/earlier.txt",
+            ],
+        );
+        fs::create_dir(dir.path().join("empty")).unwrap();
+
+        let crawl_data = crawl_data(&["docs/a.txt", "b.iso"], &["docs", "empty"]);
+        let plan = plan_sync(
+            &crawl_data,
+            output_dir,
+            trash_dir.to_str(),
+            &root_url(),
+            &[],
+        )
+        .unwrap();
+
+        // Partial downloads of crawled files and the trash directory are kept
+        assert_eq!(
+            plan.files,
+            ["docs/old.txt", "gone/deeper/c.txt", "stale.part"]
+        );
+        assert_eq!(plan.directories, ["gone/deeper", "gone"]);
+
+        let removed = apply_sync(&plan, output_dir, trash_dir.to_str())
+            .await
+            .unwrap();
+        assert_eq!(removed, (3, 2));
+
+        assert!(dir.path().join("docs/a.txt").exists());
+        assert!(dir.path().join("b.iso.part").exists());
+        assert!(dir.path().join("empty").is_dir());
+        assert!(!dir.path().join("gone").exists());
+        assert!(trash_dir.join("gone/deeper/c.txt").exists());
+        assert!(trash_dir.join("earlier.txt").exists());
+    }
+
+    #[test]
+    fn counts_only_files_against_max_delete() {
+        let plan = SyncPlan {
+            files: vec!["a.txt".to_string(), "b.txt".to_string()],
+            directories: vec!["c".to_string(), "d".to_string(), "e".to_string()],
+        };
+
+        assert!(!plan.exceeds_max_delete(2));
+        assert!(plan.exceeds_max_delete(1));
+    }
+
+    #[test]
+    fn refuses_failures_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().to_str().unwrap();
+        create_files(dir.path(), &["a.txt", "b.txt"]);
+
+        let mut crawl_data = crawl_data(&["a.txt"], &[]);
+        crawl_data.failures.push(FailedDownload {
+            url: format!("{}a.txt", root_url()),
+            kind: FailureKind::Status,
+            status: Some(503),
+            message: "503 Service Unavailable".to_string(),
+        });
+
+        assert!(plan_sync(&crawl_data, output_dir, None, &root_url(), &[]).is_err());
+    }
+
+    #[test]
+    fn keeps_files_excluded_by_the_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().to_str().unwrap();
+        create_files(
+            dir.path(),
+            &["a.txt", "old.txt", "big.iso", "private/secret.txt"],
+        );
+
+        let filters = [
+            FilterRule {
+                rule_type: RuleType::Exclude,
+                pattern: "*.iso".to_string(),
+            },
+            FilterRule {
+                rule_type: RuleType::Exclude,
+                pattern: "/pub/private/*".to_string(),
+            },
+            FilterRule {
+                rule_type: RuleType::Include,
+                pattern: "*".to_string(),
+            },
+        ];
+
+        let crawl_data = crawl_data(&["a.txt"], &[]);
+        let plan = plan_sync(&crawl_data, output_dir, None, &root_url(), &filters).unwrap();
+
+        assert_eq!(plan.files, ["old.txt"]);
+        assert!(plan.directories.is_empty());
+    }
+
+    #[test]
+    fn keeps_directories_that_could_not_be_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().to_str().unwrap();
+        create_files(
+            dir.path(),
+            &[
+                "a.txt",
+                "old.txt",
+                "locked dir/b.txt",
+                "locked dir/deeper/c.txt",
+            ],
+        );
+
+        let mut crawl_data = crawl_data(&["a.txt"], &["locked dir"]);
+        crawl_data.failed_directories.push(FailedDirectory {
+            url: format!("{}locked%20dir/", root_url()),
+            path: "locked%20dir".to_string(),
+            message: "Listing failed: 403 Forbidden".to_string(),
+        });
+        let plan = plan_sync(&crawl_data, output_dir, None, &root_url(), &[]).unwrap();
+
+        assert_eq!(plan.files, ["old.txt"]);
+        assert!(plan.directories.is_empty());
+    }
+}
@@ -1,8 +1,14 @@
-use std::{io::Write, process};
+use std::{collections::HashMap, io::Write, path::Path, process};
 
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{DateTime, Utc};
 use glob::Pattern;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use reqwest::{
-    header::{HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, REFERER},
+    header::{
+        HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, AUTHORIZATION,
+        LAST_MODIFIED, REFERER,
+    },
     Client, Url,
 };
 use tokio::io::{AsyncBufReadExt, BufReader};
@@ -15,10 +21,15 @@ use tracing::{debug, info, warn};
 use crate::{
     config::{FilterRule, RuleType},
     crawl_data::DownloadData,
+    summary::ExitStatus,
 };
 
 /// Create Http Client with custom headers
-pub fn create_http_client(user_agent: &str) -> Client {
+pub fn create_http_client(
+    user_agent: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> Client {
     let mut headers = HeaderMap::new();
     headers.insert(
         ACCEPT,
@@ -38,6 +49,15 @@ pub fn create_http_client(user_agent: &str) -> Client {
         HeaderValue::from_str(user_agent).unwrap(),
     );
 
+    if let Some(username) = username {
+        let credentials = format!("{}:{}", username, password.unwrap_or(""));
+        let mut value =
+            HeaderValue::from_str(&format!("Basic {}", BASE64_STANDARD.encode(credentials)))
+                .unwrap();
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+
     Client::builder()
         .default_headers(headers)
         .gzip(true)
@@ -48,12 +68,13 @@ pub fn create_http_client(user_agent: &str) -> Client {
         .unwrap()
 }
 
-/// Displays the files and total size, then prompts the user for confirmation.
+/// Displays the files and total size, then prompts the user for confirmation. Returns false if
+/// the user cancels the download.
 pub async fn display_prompt(
     files: &[DownloadData],
     total_size: u64,
     skip_prompt: bool,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
     // // Display the files to download
     // info!("Files to download:");
     // for file in files {
@@ -65,7 +86,7 @@ pub async fn display_prompt(
         info!("Number of files to download: {}", files.len());
         info!("Total size: {} bytes", format_size(total_size));
         info!("Skipping prompt due to --yes/-y flag.");
-        return Ok(());
+        return Ok(true);
     }
 
     // Save the file list to a temporary file which will be deleted after this function
@@ -118,20 +139,47 @@ pub async fn display_prompt(
     // If the input is 'n' or 'no', cancel the download
     if user_input == "n" || user_input == "no" {
         info!("Download canceled.");
-        // On Windows, the console window closes immediately after the program exits.
-        // To prevent this, we wait for user input before exiting.
-        #[cfg(windows)]
-        {
-            use std::io::prelude::*;
-            info!("Press Enter to exit...");
-            let _ = std::io::stdin().read(&mut [0u8]).unwrap();
-        }
-        process::exit(0);
+        return Ok(false);
     }
 
     // If the input is empty or 'y'/'yes', proceed
     info!("Proceeding with download...");
-    Ok(())
+    Ok(true)
+}
+
+/// Exits the program with the exit code of the status.
+pub fn exit(status: ExitStatus) -> ! {
+    // On Windows, the console window closes immediately after the program exits.
+    // To prevent this, we wait for user input before exiting.
+    #[cfg(windows)]
+    {
+        use std::io::prelude::*;
+        info!("Press Enter to exit...");
+        let _ = std::io::stdin().read(&mut [0u8]).unwrap();
+    }
+    process::exit(status.code())
+}
+
+/// Characters that must be percent-encoded in a URL path segment.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Percent-encodes each segment of a slash-separated path.
+pub fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
 }
 
 /// Helper function to check if a URL should be skipped based on predefined conditions.
@@ -144,40 +192,55 @@ pub fn should_skip_url(href: &str) -> bool {
         || href.starts_with('?')
 }
 
+/// File metadata reported by the server in response to a HEAD request.
+#[derive(Debug, Default)]
+pub struct RemoteMetadata {
+    pub size: Option<u64>,
+    pub modified: Option<DateTime<Utc>>,
+}
+
 async fn action(
     client: &Client,
     url: &Url,
-) -> Result<u64, RetryError<Box<dyn std::error::Error + Send + Sync>>> {
+) -> Result<RemoteMetadata, RetryError<Box<dyn std::error::Error + Send + Sync>>> {
     let response = client.head(url.clone()).send().await.map_err(|e| {
         RetryError::transient(Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
     })?;
+
+    let mut metadata = RemoteMetadata::default();
+
     if response.status().is_success() {
         if let Some(content_length) = response.headers().get(reqwest::header::CONTENT_LENGTH) {
-            if let Ok(size) = content_length
+            metadata.size = content_length
                 .to_str()
                 .map_err(|e| {
                     RetryError::transient(Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
                 })?
                 .parse::<u64>()
-            {
-                return Ok(size);
-            }
+                .ok();
         }
+
+        metadata.modified = parse_last_modified(response.headers());
     }
-    warn!("Failed to get file size for: {}", url);
-    Ok(0) // Return 0 if the size is not available
+
+    if metadata.size.is_none() {
+        warn!("Failed to get file size for: {}", url);
+    }
+
+    Ok(metadata)
 }
 
 #[allow(clippy::borrowed_box)] // it forces a &Box lmao
 fn notify(err: &Box<dyn std::error::Error + Send + Sync>, duration: std::time::Duration) {
-    warn!("Failed to get file size. Retrying... Error {err} occurred at {duration:?}");
+    warn!("Failed to get file metadata. Retrying... Error {err} occurred at {duration:?}");
 }
 
-/// Returns the file size from the Content-Length header (if available).
-pub async fn get_file_size(
+/// Returns the file size from the Content-Length header and the modification time from the
+/// Last-Modified header (if available).
+pub async fn get_file_metadata(
     client: &Client,
     url: &Url,
-) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<RemoteMetadata, Box<dyn std::error::Error + Send + Sync>> {
     let retry_strategy = ExponentialBackoff::from_millis(10)
         .factor(1)
         .max_delay_millis(100)
@@ -188,6 +251,14 @@ pub async fn get_file_size(
     Retry::spawn_notify(retry_strategy, || action(client, url), notify).await
 }
 
+/// Parses the Last-Modified header of a response.
+pub fn parse_last_modified(headers: &HeaderMap) -> Option<DateTime<Utc>> {
+    let value = headers.get(LAST_MODIFIED)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
 /// Formats a byte size into a human-readable format (e.g., "10.5 MB").
 pub fn format_size(size: u64) -> String {
     const KB: u64 = 1024;
@@ -252,3 +323,67 @@ pub fn truncate_string(s: &str, max_length: usize) -> String {
         s.to_string()
     }
 }
+
+/// Lists all files below the directory, returning their sizes keyed by their path relative to
+/// the directory, with `/` as the separator.
+pub fn walk_files(dir: &str) -> std::io::Result<HashMap<String, u64>> {
+    let mut files = HashMap::new();
+    walk(dir, &mut |path, metadata| {
+        if metadata.is_file() {
+            files.insert(path, metadata.len());
+        }
+    })?;
+    Ok(files)
+}
+
+/// Lists all directories below the directory, as paths relative to the directory with `/` as
+/// the separator.
+pub fn walk_directories(dir: &str) -> std::io::Result<Vec<String>> {
+    let mut directories = Vec::new();
+    walk(dir, &mut |path, metadata| {
+        if metadata.is_dir() {
+            directories.push(path);
+        }
+    })?;
+    Ok(directories)
+}
+
+/// Calls `visit` with the relative path and metadata of every entry below the directory.
+/// Symbolic links are not followed.
+fn walk(dir: &str, visit: &mut dyn FnMut(String, std::fs::Metadata)) -> std::io::Result<()> {
+    let mut pending = vec![Path::new(dir).to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = std::fs::symlink_metadata(&path)?;
+            if metadata.is_dir() {
+                pending.push(path.clone());
+            }
+
+            let relative = path.strip_prefix(dir).unwrap_or(&path);
+            let relative = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            visit(relative, metadata);
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves a file, creating the parent directories of the destination. Falls back to copying
+/// when the destination is on another file system.
+pub async fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    if tokio::fs::rename(from, to).await.is_err() {
+        tokio::fs::copy(from, to).await?;
+        tokio::fs::remove_file(from).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+
+use crate::{crawl_data::CrawlData, utils::walk_files};
+
+/// The result of auditing a local mirror against its crawl data.
+#[derive(Debug, Serialize)]
+pub struct VerifyReport {
+    pub output_dir: String,
+    /// Number of files in the crawl data.
+    pub checked: usize,
+    /// Number of files that exist with the crawled size.
+    pub ok: usize,
+    /// Number of files that exist, but whose size wasn't recorded by the crawl.
+    pub size_unknown: usize,
+    /// Files in the crawl data that don't exist locally.
+    pub missing: Vec<String>,
+    /// Files whose local size doesn't match the crawled size.
+    pub wrong_size: Vec<WrongSize>,
+    /// Partial downloads (`.part` files) of files in the crawl data.
+    pub partial: Vec<String>,
+    /// Local files that aren't in the crawl data.
+    pub extra: Vec<String>,
+}
+
+/// A local file whose size doesn't match the crawled size.
+#[derive(Debug, Serialize)]
+pub struct WrongSize {
+    pub path: String,
+    pub expected: u64,
+    /// Whether `expected` is exact, rather than rounded by the directory listing.
+    pub expected_exact: bool,
+    pub actual: u64,
+}
+
+impl VerifyReport {
+    /// Returns true if the mirror matches the crawl data.
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty()
+            && self.wrong_size.is_empty()
+            && self.partial.is_empty()
+            && self.extra.is_empty()
+    }
+}
+
+/// Compares the files in the output directory with the crawl data, using only the sizes recorded
+/// in the crawl data, so no request is sent to the server.
+pub fn verify_mirror(
+    crawl_data: &CrawlData,
+    output_dir: &str,
+) -> Result<VerifyReport, Box<dyn std::error::Error + Send + Sync>> {
+    // A missing output directory just means that every file is missing
+    let mut local_files = if Path::new(output_dir).is_dir() {
+        walk_files(output_dir)?
+    } else {
+        HashMap::new()
+    };
+
+    let mut report = VerifyReport {
+        output_dir: output_dir.to_string(),
+        checked: crawl_data.download_list.len(),
+        ok: 0,
+        size_unknown: 0,
+        missing: Vec::new(),
+        wrong_size: Vec::new(),
+        partial: Vec::new(),
+        extra: Vec::new(),
+    };
+
+    let mut expected = HashSet::new();
+
+    for file in &crawl_data.download_list {
+        let path = percent_decode_str(&file.output_dir)
+            .decode_utf8_lossy()
+            .into_owned();
+
+        match local_files.remove(&path) {
+            None => report.missing.push(path.clone()),
+            Some(actual) => match file.size {
+                None => report.size_unknown += 1,
+                Some(_) if file.size_matches(actual) => report.ok += 1,
+                Some(size) => report.wrong_size.push(WrongSize {
+                    path: path.clone(),
+                    expected: size,
+                    expected_exact: file.size_exact,
+                    actual,
+                }),
+            },
+        }
+
+        expected.insert(path);
+    }
+
+    // Whatever is left locally isn't part of the crawl
+    for path in local_files.into_keys() {
+        match path.strip_suffix(".part") {
+            Some(target) if expected.contains(target) => report.partial.push(path),
+            _ => report.extra.push(path),
+        }
+    }
+
+    report.missing.sort();
+    report.wrong_size.sort_by(|a, b| a.path.cmp(&b.path));
+    report.partial.sort();
+    report.extra.sort();
+
+    Ok(report)
+}
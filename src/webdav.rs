@@ -0,0 +1,284 @@
+use chrono::{DateTime, Utc};
+use reqwest::{
+    header::{HeaderValue, CONTENT_TYPE},
+    Client, Method, Url,
+};
+use tracing::trace;
+
+use crate::{
+    index_parser::{child_entry, ListingEntry},
+    network::send_with_retry,
+    xml::XmlElement,
+};
+
+/// The properties requested for every member of a collection.
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propfind xmlns:D="DAV:">
+  <D:prop>
+    <D:resourcetype/>
+    <D:getcontentlength/>
+    <D:getlastmodified/>
+    <D:getetag/>
+  </D:prop>
+</D:propfind>"#;
+
+/// Lists the members of the WebDAV collection at `url` with a single `Depth: 1` PROPFIND.
+pub async fn list_collection(
+    client: &Client,
+    url: &Url,
+) -> Result<Vec<ListingEntry>, Box<dyn std::error::Error + Send + Sync>> {
+    trace!("Listing collection: {}", url);
+
+    let request = client
+        .request(Method::from_bytes(b"PROPFIND")?, url.clone())
+        .header("Depth", "1")
+        .header(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/xml; charset=utf-8"),
+        )
+        .body(PROPFIND_BODY);
+
+    let response = send_with_retry(request).await?;
+    let status = response.status();
+
+    if status.as_u16() != 207 {
+        return Err(format!("PROPFIND {} failed: {}", url, status).into());
+    }
+
+    let multistatus = XmlElement::parse(&response.text().await?)?;
+
+    let mut entries = Vec::new();
+
+    for member in multistatus.children_named("response") {
+        // The collection itself is part of the response, and is skipped here since it
+        // isn't a child of itself
+        let Some(mut entry) = member
+            .child_text("href")
+            .and_then(|href| child_entry(url, href.trim()))
+        else {
+            continue;
+        };
+
+        // Only read properties from the propstat blocks the server could resolve
+        let props = member
+            .children_named("propstat")
+            .filter(|propstat| {
+                propstat
+                    .child_text("status")
+                    .is_none_or(|status| status.contains(" 200 "))
+            })
+            .filter_map(|propstat| propstat.child("prop"));
+
+        for prop in props {
+            if prop
+                .child("resourcetype")
+                .is_some_and(|resource_type| resource_type.child("collection").is_some())
+            {
+                entry.set_directory();
+            }
+
+            if let Some(size) = prop
+                .child_text("getcontentlength")
+                .and_then(|size| size.trim().parse().ok())
+            {
+                entry.size = Some(size);
+                entry.size_exact = true;
+            }
+
+            if let Some(modified) = prop
+                .child_text("getlastmodified")
+                .and_then(|modified| DateTime::parse_from_rfc2822(modified.trim()).ok())
+            {
+                entry.mtime = Some(modified.with_timezone(&Utc));
+                entry.mtime_exact = true;
+            }
+
+            if let Some(etag) = prop.child_text("getetag").filter(|etag| !etag.is_empty()) {
+                entry.etag = Some(etag.to_string());
+            }
+        }
+
+        if entry.is_dir {
+            entry.size = None;
+            entry.size_exact = false;
+        }
+
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    /// Starts a server answering every request with a 207 response carrying `body`, in which
+    /// `{origin}` is replaced by the server's origin. Returns the server's origin and the heads
+    /// of the requests received so far.
+    async fn mock_server(body: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        let body = body.replace("{origin}", &origin);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let head = read_request(&mut stream).await;
+                received.lock().unwrap().push(head);
+
+                let response = format!(
+                    "HTTP/1.1 207 Multi-Status\r\nContent-Type: application/xml; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (origin, requests)
+    }
+
+    /// Reads a request with its body, so closing the connection afterwards doesn't reset it.
+    /// Returns the request line and headers, lowercased.
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        let head_end = loop {
+            if let Some(end) = request.windows(4).position(|bytes| bytes == b"\r\n\r\n") {
+                break end + 4;
+            }
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert_ne!(read, 0, "connection closed before the end of the request");
+            request.extend_from_slice(&buffer[..read]);
+        };
+
+        let head = String::from_utf8_lossy(&request[..head_end]).to_lowercase();
+        let length: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .map_or(0, |length| length.trim().parse().unwrap());
+        while request.len() < head_end + length {
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert_ne!(read, 0, "connection closed before the end of the request");
+            request.extend_from_slice(&buffer[..read]);
+        }
+
+        head
+    }
+
+    #[tokio::test]
+    async fn lists_collection_members() {
+        let (origin, requests) = mock_server(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:">
+  <D:response>
+    <D:href>/dav/docs/</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:resourcetype><D:collection/></D:resourcetype>
+        <D:getlastmodified>Fri, 15 Nov 2024 10:20:00 GMT</D:getlastmodified>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+  <D:response>
+    <D:href>{origin}/dav/docs/report%202024.pdf</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:resourcetype/>
+        <D:getcontentlength>1024</D:getcontentlength>
+        <D:getlastmodified>Fri, 15 Nov 2024 10:21:00 GMT</D:getlastmodified>
+        <D:getetag>"5e1-62"</D:getetag>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+  <D:response>
+    <D:href>/dav/docs/images</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:resourcetype><D:collection/></D:resourcetype>
+        <D:getcontentlength>4096</D:getcontentlength>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+    <D:propstat>
+      <D:prop>
+        <D:getetag/>
+      </D:prop>
+      <D:status>HTTP/1.1 404 Not Found</D:status>
+    </D:propstat>
+  </D:response>
+  <D:response>
+    <D:href>notes.txt</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:getcontentlength>12</D:getcontentlength>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+    <D:propstat>
+      <D:prop>
+        <D:resourcetype><D:collection/></D:resourcetype>
+        <D:getcontentlength>0</D:getcontentlength>
+      </D:prop>
+      <D:status>HTTP/1.1 403 Forbidden</D:status>
+    </D:propstat>
+  </D:response>
+</D:multistatus>"#,
+        )
+        .await;
+
+        let url = Url::parse(&format!("{}/dav/docs/", origin)).unwrap();
+        let entries = list_collection(&Client::new(), &url).await.unwrap();
+
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["report%202024.pdf", "images", "notes.txt"]);
+
+        assert!(!entries[0].is_dir);
+        assert_eq!(entries[0].size, Some(1024));
+        assert!(entries[0].size_exact);
+        assert_eq!(entries[0].etag.as_deref(), Some("\"5e1-62\""));
+        assert_eq!(
+            entries[0].mtime,
+            Some(
+                DateTime::parse_from_rfc3339("2024-11-15T10:21:00Z")
+                    .unwrap()
+                    .with_timezone(&Utc)
+            )
+        );
+
+        // Collections get a trailing slash, and no size
+        assert!(entries[1].is_dir);
+        assert_eq!(
+            entries[1].url.as_str(),
+            format!("{}/dav/docs/images/", origin)
+        );
+        assert_eq!(entries[1].size, None);
+        assert!(!entries[1].size_exact);
+
+        // Properties from a failed propstat block are ignored
+        assert!(!entries[2].is_dir);
+        assert_eq!(
+            entries[2].url.as_str(),
+            format!("{}/dav/docs/notes.txt", origin)
+        );
+        assert_eq!(entries[2].size, Some(12));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("propfind /dav/docs/ http/1.1\r\n"));
+        assert!(requests[0].contains("\r\ndepth: 1\r\n"));
+    }
+}
@@ -0,0 +1,97 @@
+use quick_xml::{events::Event, Reader};
+
+/// A minimal in-memory XML element tree.
+///
+/// Elements and attributes are keyed by their local names, so documents using different
+/// namespace prefixes (`D:href`, `d:href`, `href`) are read the same way.
+#[derive(Debug, Default)]
+pub struct XmlElement {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub text: String,
+    pub children: Vec<XmlElement>,
+}
+
+impl XmlElement {
+    /// Parses an XML document and returns its root element.
+    pub fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut reader = Reader::from_str(content);
+        reader.config_mut().trim_text(true);
+
+        let mut stack: Vec<XmlElement> = Vec::new();
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(start) => stack.push(Self::from_start(&start)?),
+                Event::Empty(start) => {
+                    let element = Self::from_start(&start)?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                Event::Text(text) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&text.unescape()?);
+                    }
+                }
+                Event::CData(data) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&String::from_utf8_lossy(&data));
+                    }
+                }
+                Event::End(_) => {
+                    let element = stack.pop().ok_or("Unexpected closing tag")?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                Event::Eof => return Err("Unexpected end of XML document".into()),
+                _ => {}
+            }
+        }
+    }
+
+    fn from_start(
+        start: &quick_xml::events::BytesStart,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut element = XmlElement {
+            name: String::from_utf8_lossy(start.local_name().as_ref()).into_owned(),
+            ..Default::default()
+        };
+
+        for attribute in start.attributes() {
+            let attribute = attribute?;
+            element.attributes.push((
+                String::from_utf8_lossy(attribute.key.local_name().as_ref()).into_owned(),
+                attribute.unescape_value()?.into_owned(),
+            ));
+        }
+
+        Ok(element)
+    }
+
+    /// Returns the value of the attribute with the given local name.
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the first child element with the given local name.
+    pub fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Returns all child elements with the given local name.
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlElement> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Returns the text of the first child element with the given local name.
+    pub fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.as_str())
+    }
+}